@@ -1,8 +1,8 @@
 #![allow(unused)] // features
 
 use crate::SubstitutingString;
-use color_eyre::eyre::Result;
-use serde::Deserialize;
+use color_eyre::eyre::{OptionExt, Result};
+use serde::{Deserialize, Serialize};
 use serde_inline_default::serde_inline_default;
 use serenity::{
     all::RuleId,
@@ -34,7 +34,10 @@ pub struct Config {
 impl Config {
     #[inline]
     pub fn from_str(source: &str) -> Result<Config> {
-        Ok(toml::from_str(source)?)
+        let mut config: Config = toml::from_str(source)?;
+        #[cfg(feature = "starboard")]
+        config.starboard.convert_legacy_board()?;
+        Ok(config)
     }
 
     pub async fn from_file(path: impl AsRef<Path>) -> Result<Config> {
@@ -49,6 +52,7 @@ pub struct MongodbConfig {
     pub database: SubstitutingString,
 }
 
+#[serde_inline_default]
 #[derive(Debug, Clone, Deserialize)]
 pub struct DiscordConfig {
     pub command_prefix: SubstitutingString,
@@ -67,19 +71,207 @@ pub struct DiscordConfig {
     #[serde(default)]
     pub enforce_automods: HashSet<GuildId>,
     #[serde(default)]
+    pub automod: HashMap<GuildId, AutomodConfig>,
+    #[serde(default)]
     pub volatiles: Vec<VolatileConfig>,
+    #[serde(default)]
+    pub quotas: Vec<QuotaConfig>,
+    #[serde(default)]
+    pub sticky_roles: HashMap<GuildId, StickyRolesConfig>,
+    #[serde(default)]
+    pub autoroles: HashMap<GuildId, AutorolesConfig>,
+    /// Seconds between full snapshots of all members' stickies, 0 to disable.
+    #[serde_inline_default(6 * 60 * 60)]
+    pub sticky_roles_reconcile_interval: u64,
+    /// By qualified command name, subcommands fall back to the policy of their parent.
+    #[serde(default)]
+    pub command_policies: HashMap<String, CommandPolicy>,
+}
+
+/// Where and by whom a command can be used. The default only allows the command channels.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CommandPolicy {
+    /// Channels the command is allowed in, the command channels if none of them are in the guild.
+    #[serde(default)]
+    pub channels: HashSet<ChannelId>,
+    /// Allow the command in every channel, except the denied ones.
+    #[serde(default)]
+    pub anywhere: bool,
+    #[serde(default)]
+    pub deny_channels: HashSet<ChannelId>,
+    /// Only allow the command in NSFW channels.
+    #[serde(default)]
+    pub nsfw: bool,
+    /// Members need one of these roles, anyone can use the command if empty.
+    #[serde(default)]
+    pub roles: HashSet<RoleId>,
+    #[serde(default)]
+    pub dm: bool,
 }
 
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AutorolesConfig {
+    /// Granted as soon as a member joins.
+    #[serde(default)]
+    pub roles: HashSet<RoleId>,
+    #[serde(default)]
+    pub delayed: Vec<DelayedRoleConfig>,
+}
+
+/// Role granted some time after joining, or only once the member passed membership screening.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DelayedRoleConfig {
+    pub role: RoleId,
+    /// Seconds after joining.
+    #[serde(default)]
+    pub delay: u64,
+    #[serde(default)]
+    pub screening: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct StickyRolesConfig {
+    /// Only restore these roles, if set.
+    #[serde(default)]
+    pub allow: Option<HashSet<RoleId>>,
+    /// Never restore these roles.
+    #[serde(default)]
+    pub deny: HashSet<RoleId>,
+    /// Always restore these roles regardless of `allow` and `deny`, e.g. mutes.
+    #[serde(default)]
+    pub always: HashSet<RoleId>,
+    #[serde(default)]
+    pub restore_nickname: bool,
+    /// Don't restore anything to members who left more than this many days ago.
+    #[serde(default)]
+    pub expiry: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AutomodConfig {
+    #[serde(default)]
+    pub normalize: NormalizeConfig,
+    #[serde(default)]
+    pub escalation: Vec<EscalationConfig>,
+    #[serde(default)]
+    pub rules: Vec<ContentRuleConfig>,
+}
+
+/// Automod rule enforced by the bot itself, for things Discord's own automod can't express.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ContentRuleConfig {
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: ContentRuleKind,
+    pub actions: Vec<AutomodAction>,
+    #[serde(default)]
+    pub exempt_roles: HashSet<RoleId>,
+    #[serde(default)]
+    pub exempt_channels: HashSet<ChannelId>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentRuleKind {
+    /// Links to domains (or their subdomains) not in `domains`, in `channels` (or everywhere if empty).
+    LinkDomains {
+        #[serde(default)]
+        channels: HashSet<ChannelId>,
+        domains: HashSet<String>,
+    },
+    /// Discord invite links.
+    Invites,
+    /// Messages with more than `max` lines.
+    MaxLines { max: usize },
+    /// The same character repeated more than `max` times in a row.
+    RepeatedCharacters { max: usize },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum AutomodAction {
+    Block {
+        #[serde(default)]
+        message: Option<String>,
+    },
+    Alert,
+}
+
+/// Extra action taken once a user has matched automod rules `count` times within `within` seconds.
+#[derive(Debug, Clone, Deserialize)]
+pub struct EscalationConfig {
+    pub count: u64,
+    pub within: u64,
+    #[serde(flatten)]
+    pub action: EscalationAction,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum EscalationAction {
+    Timeout { duration: u64 },
+    Kick,
+    Ban,
+}
+
+/// Normalization steps applied to content before matching it against automod rules.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct NormalizeConfig {
+    /// Remove zero-width and other invisible characters.
+    #[serde(default)]
+    pub invisible: bool,
+    /// Apply Unicode compatibility normalization, e.g. fullwidth letters to ASCII.
+    #[serde(default)]
+    pub compatibility: bool,
+    /// Strip combining marks, e.g. accents and "zalgo" text.
+    #[serde(default)]
+    pub combining: bool,
+    /// Replace Cyrillic and Greek lookalikes with Latin letters.
+    #[serde(default)]
+    pub confusables: bool,
+    /// Replace leetspeak digits and symbols with letters.
+    #[serde(default)]
+    pub leetspeak: bool,
+}
+
+impl NormalizeConfig {
+    #[inline]
+    pub fn is_enabled(self) -> bool {
+        self.invisible || self.compatibility || self.combining || self.confusables || self.leetspeak
+    }
+}
+
+#[serde_inline_default]
 #[derive(Debug, Clone, Deserialize)]
 pub struct VolatileConfig {
     pub channel: ChannelId,
     pub role: RoleId,
+    /// Seconds after a user's first post until they can post again, never if unset.
+    #[serde(default)]
+    pub window: Option<u64>,
+    /// Posts allowed per window.
+    #[serde_inline_default(1)]
+    pub posts: u64,
+}
+
+/// At most `max` messages per user within any `per` seconds.
+#[derive(Debug, Clone, Deserialize)]
+pub struct QuotaConfig {
+    pub channel: ChannelId,
+    pub max: usize,
+    pub per: u64,
+    #[serde(default)]
+    pub exempt_roles: HashSet<RoleId>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct GibConfig {
     pub endpoint: SubstitutingString,
     pub user_agent: SubstitutingString,
+    /// Tag search used to autocomplete slash commands, none if unset.
+    #[serde(default)]
+    pub tags_endpoint: Option<SubstitutingString>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -122,12 +314,21 @@ pub struct OpenAiConfig {
     pub prompt: SubstitutingString,
 }
 
+#[serde_inline_default]
 #[derive(Debug, Clone, Deserialize)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct StarboardConfig {
-    pub emoji: SubstitutingString,
-    pub threshold: u64,
+    #[serde(default)]
+    pub boards: Vec<BoardConfig>,
+    /// Legacy single board, converted into `boards` on load.
+    #[serde(default)]
+    pub emoji: Option<SubstitutingString>,
+    #[serde(default)]
+    pub threshold: Option<u64>,
     #[serde(default)]
     pub max_threshold: Option<u64>,
+    /// Legacy board channels, one per guild.
+    #[serde(default)]
     pub channels: HashSet<ChannelId>,
     #[serde(default)]
     pub ignore_stars: HashSet<RoleId>,
@@ -135,6 +336,100 @@ pub struct StarboardConfig {
     pub ignore_messages: HashSet<RoleId>,
     #[serde(default)]
     pub ignore_channels: HashSet<RoleId>,
+    /// Remove messages from the board when they drop below the threshold again.
+    #[serde(default)]
+    pub delete_below_threshold: bool,
+    /// Remove messages from the board when the original is deleted.
+    #[serde(default)]
+    pub delete_with_original: bool,
+    /// Don't count authors starring their own messages.
+    #[serde(default)]
+    pub exclude_author: bool,
+    #[serde(default)]
+    pub exclude_bots: bool,
+    /// Number of recent messages per channel to revisit on startup, 0 to disable.
+    #[serde_inline_default(100)]
+    pub catch_up: u8,
+    /// How much stars from members with these roles count, the highest applies. 1 for everyone else.
+    #[serde(default)]
+    pub role_weights: HashMap<RoleId, u64>,
+    /// Seconds someone has to be a member before their stars count.
+    #[serde(default)]
+    pub min_member_age: Option<u64>,
+    /// Messages someone has to have sent in the guild before their stars count.
+    #[serde(default)]
+    pub min_messages: Option<usize>,
+}
+
+impl StarboardConfig {
+    /// Name of the board converted from the legacy settings.
+    pub const LEGACY_BOARD_NAME: &str = "stars";
+
+    /// Turns the settings from before there were multiple boards into a board per channel.
+    fn convert_legacy_board(&mut self) -> Result<()> {
+        let Some(emoji) = self.emoji.take() else {
+            return Ok(());
+        };
+        let threshold = self
+            .threshold
+            .take()
+            .ok_or_eyre("starboard.emoji is set without starboard.threshold")?;
+        for channel in self.channels.drain() {
+            self.boards.push(BoardConfig {
+                name: String::from(Self::LEGACY_BOARD_NAME),
+                emoji: emoji.clone(),
+                threshold,
+                max_threshold: self.max_threshold,
+                channel,
+                sources: HashSet::new(),
+                nsfw: NsfwPolicy::default(),
+                mode: RenderMode::default(),
+            });
+        }
+        self.max_threshold = None;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BoardConfig {
+    /// Unique name, stored with the messages on the board.
+    pub name: String,
+    pub emoji: SubstitutingString,
+    pub threshold: u64,
+    /// Pick a random threshold between `threshold` and this for each message.
+    #[serde(default)]
+    pub max_threshold: Option<u64>,
+    pub channel: ChannelId,
+    /// Only messages from these channels or their children, all if empty.
+    #[serde(default)]
+    pub sources: HashSet<ChannelId>,
+    #[serde(default)]
+    pub nsfw: NsfwPolicy,
+    #[serde(default)]
+    pub mode: RenderMode,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RenderMode {
+    /// Re-upload the attachments and rebuild the embeds, under a header with the stars.
+    #[default]
+    Upload,
+    /// Natively forward the message, without a header.
+    Forward,
+    /// Natively forward the message, followed by a header replying to it.
+    Hybrid,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NsfwPolicy {
+    /// NSFW messages only if the board channel is NSFW too.
+    #[default]
+    Inherit,
+    Exclude,
+    Only,
 }
 
 #[cfg(test)]
@@ -144,4 +439,24 @@ mod tests {
     async fn test_config() {
         super::Config::from_file("config.toml").await.unwrap();
     }
+
+    #[cfg(feature = "starboard")]
+    #[test]
+    fn legacy_starboard() {
+        let mut config: super::StarboardConfig = toml::from_str(
+            r#"
+            emoji = "⭐"
+            threshold = 3
+            channels = [1]
+            "#,
+        )
+        .unwrap();
+        config.convert_legacy_board().unwrap();
+        assert_eq!(config.boards.len(), 1);
+        let board = &config.boards[0];
+        assert_eq!(board.name, super::StarboardConfig::LEGACY_BOARD_NAME);
+        assert_eq!(board.threshold, 3);
+        assert_eq!(board.channel.get(), 1);
+        assert!(config.channels.is_empty());
+    }
 }
@@ -0,0 +1,112 @@
+use crate::config::{ContentRuleConfig, ContentRuleKind};
+use itertools::Itertools;
+use lazy_regex::{regex, regex_is_match};
+use serenity::all::{ChannelId, RoleId};
+
+fn link_hosts(content: &str) -> impl Iterator<Item = String> + '_ {
+    regex!(r"(?i)\bhttps?://(?:[^/\s<>@]*@)?(?P<host>[^/\s<>:?#]+)")
+        .captures_iter(content)
+        .filter_map(|cap| cap.name("host"))
+        .map(|host| host.as_str().trim_end_matches('.').to_lowercase())
+}
+
+fn is_allowed_domain<'a>(host: &str, mut domains: impl Iterator<Item = &'a String>) -> bool {
+    domains.any(|domain| {
+        let domain = domain.to_lowercase();
+        host == domain
+            || host
+                .strip_suffix(&domain)
+                .is_some_and(|sub| sub.ends_with('.'))
+    })
+}
+
+fn longest_run(content: &str) -> usize {
+    content
+        .chars()
+        .chunk_by(|c| *c)
+        .into_iter()
+        .map(|(_, run)| run.count())
+        .max()
+        .unwrap_or(0)
+}
+
+impl ContentRuleKind {
+    /// Whether `content` posted on `channel_id` breaks the rule.
+    pub fn matches(&self, channel_id: ChannelId, content: &str) -> bool {
+        match self {
+            Self::LinkDomains { channels, domains } => {
+                (channels.is_empty() || channels.contains(&channel_id))
+                    && link_hosts(content).any(|host| !is_allowed_domain(&host, domains.iter()))
+            }
+            Self::Invites => regex_is_match!(
+                r"(?i)\b(?:discord(?:app)?\.com/invite|discord\.gg|discord\.me|dsc\.gg)/[\w-]+",
+                content
+            ),
+            Self::MaxLines { max } => content.lines().count() > *max,
+            Self::RepeatedCharacters { max } => longest_run(content) > *max,
+        }
+    }
+}
+
+impl ContentRuleConfig {
+    #[inline]
+    pub fn is_exempt(&self, channel_id: ChannelId, roles: &[RoleId]) -> bool {
+        self.exempt_channels.contains(&channel_id)
+            || roles.iter().any(|role| self.exempt_roles.contains(role))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::ContentRuleKind;
+    use maplit::hashset;
+    use serenity::all::ChannelId;
+
+    const CHANNEL: ChannelId = ChannelId::new(1);
+
+    #[test]
+    fn link_domains() {
+        let rule = ContentRuleKind::LinkDomains {
+            channels: hashset! {},
+            domains: hashset! { String::from("derpibooru.org") },
+        };
+        assert!(!rule.matches(CHANNEL, "no links here"));
+        assert!(!rule.matches(CHANNEL, "https://derpibooru.org/images/1"));
+        assert!(!rule.matches(CHANNEL, "<https://cdn.Derpibooru.org:443/x.png>"));
+        assert!(rule.matches(CHANNEL, "https://notderpibooru.org/"));
+        assert!(rule.matches(CHANNEL, "https://derpibooru.org.evil.com/"));
+        assert!(rule.matches(CHANNEL, "http://derpibooru.org@evil.com/"));
+
+        let rule = ContentRuleKind::LinkDomains {
+            channels: hashset! { ChannelId::new(2) },
+            domains: hashset! {},
+        };
+        assert!(!rule.matches(CHANNEL, "https://example.com/"));
+        assert!(rule.matches(ChannelId::new(2), "https://example.com/"));
+    }
+
+    #[test]
+    fn invites() {
+        let rule = ContentRuleKind::Invites;
+        assert!(rule.matches(CHANNEL, "join discord.gg/abc-123"));
+        assert!(rule.matches(CHANNEL, "https://discord.com/invite/abc"));
+        assert!(rule.matches(CHANNEL, "https://discordapp.com/invite/abc"));
+        assert!(!rule.matches(CHANNEL, "https://discord.com/channels/1/2/3"));
+    }
+
+    #[test]
+    fn max_lines() {
+        let rule = ContentRuleKind::MaxLines { max: 2 };
+        assert!(!rule.matches(CHANNEL, "one\ntwo"));
+        assert!(rule.matches(CHANNEL, "one\ntwo\nthree"));
+    }
+
+    #[test]
+    fn repeated_characters() {
+        let rule = ContentRuleKind::RepeatedCharacters { max: 3 };
+        assert!(!rule.matches(CHANNEL, ""));
+        assert!(!rule.matches(CHANNEL, "aaa bbb"));
+        assert!(rule.matches(CHANNEL, "aaaa"));
+        assert!(rule.matches(CHANNEL, "hello!!!!"));
+    }
+}
@@ -0,0 +1,134 @@
+use super::{
+    super::{DbKey, get_data, log_channel},
+    surfaces::Surface,
+};
+use crate::{
+    config::{AutomodConfig, EscalationAction, EscalationConfig},
+    util::format_duration_long,
+};
+use bson::{doc, serde_helpers::datetime::FromChrono04DateTime};
+use chrono::{DateTime, TimeDelta, Utc};
+use color_eyre::eyre::Result;
+use futures::TryStreamExt;
+use mongodb::Collection;
+use serde::{Deserialize, Serialize};
+use serenity::all::{Context, GuildId, Member, Message, Timestamp, UserId};
+use std::time::Duration;
+
+pub const COLLECTION_NAME: &str = "infractions";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Infraction {
+    pub guild_id: String,
+    pub user_id: String,
+    pub channel_id: String,
+    pub rule: String,
+    /// Where in the message the rule matched, see [`super::surfaces::SurfaceKind`].
+    #[serde(default)]
+    pub surface: String,
+    pub content: String,
+    #[serde(with = "FromChrono04DateTime")]
+    pub time: DateTime<Utc>,
+}
+
+async fn collection(ctx: &Context) -> Result<Collection<Infraction>> {
+    Ok(get_data::<DbKey>(ctx).await?.collection(COLLECTION_NAME))
+}
+
+pub async fn record(
+    ctx: &Context,
+    guild_id: GuildId,
+    message: &Message,
+    rule: &str,
+    surface: &Surface<'_>,
+) -> Result<()> {
+    collection(ctx)
+        .await?
+        .insert_one(Infraction {
+            guild_id: guild_id.to_string(),
+            user_id: message.author.id.to_string(),
+            channel_id: message.channel_id.to_string(),
+            rule: rule.to_owned(),
+            surface: surface.kind.to_string(),
+            content: surface.text.to_owned(),
+            time: Utc::now(),
+        })
+        .await?;
+    Ok(())
+}
+
+/// Returns the total number of infractions of the user, and the `limit` most recent ones.
+pub async fn list(
+    ctx: &Context,
+    guild_id: GuildId,
+    user_id: UserId,
+    limit: i64,
+) -> Result<(u64, Vec<Infraction>)> {
+    let collection = collection(ctx).await?;
+    let filter = doc! {
+        "guild_id": guild_id.to_string(),
+        "user_id": user_id.to_string(),
+    };
+    let total = collection.count_documents(filter.clone()).await?;
+    let recent = collection
+        .find(filter)
+        .sort(doc! { "time": -1 })
+        .limit(limit)
+        .await?
+        .try_collect()
+        .await?;
+    Ok((total, recent))
+}
+
+/// Applies the highest escalation step the member has reached, if any.
+pub async fn escalate(ctx: &Context, config: &AutomodConfig, member: &Member) -> Result<()> {
+    let collection = collection(ctx).await?;
+    let now = Utc::now();
+
+    let mut reached: Option<(&EscalationConfig, u64)> = None;
+    for step in &config.escalation {
+        let since = now - TimeDelta::seconds(i64::try_from(step.within)?);
+        let count = collection
+            .count_documents(doc! {
+                "guild_id": member.guild_id.to_string(),
+                "user_id": member.user.id.to_string(),
+                "time": { "$gte": since },
+            })
+            .await?;
+        if count >= step.count && reached.is_none_or(|(highest, _)| step.count > highest.count) {
+            reached = Some((step, count));
+        }
+    }
+    let Some((step, count)) = reached else {
+        return Ok(());
+    };
+
+    let reason = format!(
+        "{count} automod infractions within {}",
+        format_duration_long(&Duration::from_secs(step.within))
+    );
+    let action = match step.action {
+        EscalationAction::Timeout { duration } => {
+            let until = now + TimeDelta::seconds(i64::try_from(duration)?);
+            member
+                .clone()
+                .disable_communication_until_datetime(ctx, Timestamp::from(until))
+                .await?;
+            format!(
+                "timed out for {}",
+                format_duration_long(&Duration::from_secs(duration))
+            )
+        }
+        EscalationAction::Kick => {
+            member.kick_with_reason(ctx, &reason).await?;
+            String::from("kicked")
+        }
+        EscalationAction::Ban => {
+            member.ban_with_reason(ctx, 0, &reason).await?;
+            String::from("banned")
+        }
+    };
+
+    log::info!("Escalated automod for {}: {action}", member.user.tag());
+    log_channel::automod_escalated(ctx, member.guild_id, &member.user, &action, &reason).await
+}
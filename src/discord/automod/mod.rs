@@ -0,0 +1,479 @@
+use cached::{Cached, LruTtlCache, cached};
+use color_eyre::eyre::{Result, eyre};
+use itertools::Itertools;
+use lazy_regex::regex::{self, Regex, RegexSet, RegexSetBuilder};
+use serenity::all::{
+    AutomodEventType, CacheHttp, Context, GuildId, Message, MessageBuilder, MessageId, Rule,
+    Trigger, automod::Action,
+};
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::{Arc, LazyLock, Mutex, PoisonError},
+    time::Duration,
+};
+
+use super::{ConfigKey, get_data, log_channel};
+use crate::config::{AutomodAction, Config};
+
+mod content_rules;
+pub mod infractions;
+mod normalize;
+pub mod surfaces;
+
+use surfaces::Surface;
+
+/// A message keyword rule with its keywords and allow list compiled into regex sets.
+#[derive(Debug, Clone)]
+pub struct CompiledRule {
+    pub rule: Rule,
+    keywords: Vec<String>,
+    block: RegexSet,
+    allow_list: Vec<String>,
+    allow: RegexSet,
+}
+
+impl CompiledRule {
+    /// Returns `None` for rules that aren't message keyword rules, or fail to compile.
+    pub fn new(rule: Rule) -> Option<Self> {
+        if rule.event_type != AutomodEventType::MessageSend {
+            return None;
+        }
+        let Trigger::Keyword {
+            ref strings,
+            ref regex_patterns,
+            ref allow_list,
+        } = rule.trigger
+        else {
+            return None;
+        };
+
+        let (keywords, block_patterns): (Vec<_>, Vec<_>) = strings
+            .iter()
+            .map(|s| (s.clone(), wildcards_to_regex(s)))
+            .chain(regex_patterns.iter().map(|re| (re.clone(), re.clone())))
+            .filter(|(_, re)| is_valid_regex(re))
+            .unzip();
+        let (allow_list, allow_patterns): (Vec<_>, Vec<_>) = allow_list
+            .iter()
+            .map(|s| (s.clone(), wildcards_to_regex(s)))
+            .filter(|(_, re)| is_valid_regex(re))
+            .unzip();
+
+        let (block, allow) = match (compile_set(&block_patterns), compile_set(&allow_patterns)) {
+            (Ok(block), Ok(allow)) => (block, allow),
+            (Err(err), _) | (_, Err(err)) => {
+                log::warn!("Unable to compile automod rule '{}': {err:?}", rule.name);
+                return None;
+            }
+        };
+
+        Some(Self {
+            rule,
+            keywords,
+            block,
+            allow_list,
+            allow,
+        })
+    }
+
+    /// Matches the rule against `content`, ignoring exemptions.
+    pub fn matches(&self, content: &str) -> RuleMatch {
+        let keywords = self
+            .block
+            .matches(content)
+            .into_iter()
+            .map(|i| self.keywords[i].clone())
+            .collect_vec();
+        if keywords.is_empty() {
+            return RuleMatch::default();
+        }
+
+        let allowed = self
+            .allow
+            .matches(content)
+            .into_iter()
+            .map(|i| self.allow_list[i].clone())
+            .collect_vec();
+
+        RuleMatch { keywords, allowed }
+    }
+
+    /// Matches the rule against `content`, falling back to its normalized form if given.
+    pub fn matches_normalized(&self, content: &str, normalized: Option<&str>) -> RuleMatch {
+        let matched = self.matches(content);
+        if matched.is_triggered() {
+            return matched;
+        }
+        normalized
+            .map(|normalized| self.matches(normalized))
+            .filter(RuleMatch::is_triggered)
+            .unwrap_or(matched)
+    }
+}
+
+// Invalidated by rule change events, the TTL is only a fallback for events missed while disconnected.
+#[cached(
+    ttl = 3600,
+    sync_writes = "default",
+    key = "GuildId",
+    convert = "{guild}"
+)]
+async fn get_rules(ctx: &Context, guild: GuildId) -> Result<Arc<Vec<CompiledRule>>, String> {
+    let rules = guild
+        .automod_rules(ctx.http())
+        .await
+        .map_err(|err| format!("{:?}", eyre!(err)))?;
+    Ok(Arc::new(
+        rules.into_iter().filter_map(CompiledRule::new).collect(),
+    ))
+}
+
+/// Drops the compiled rules of the guild, so they get refetched on the next message.
+pub async fn invalidate_rules(guild_id: GuildId) {
+    GET_RULES.write().await.cache_remove(&guild_id);
+}
+
+fn wildcards_to_regex(s: impl AsRef<str>) -> String {
+    let s = s.as_ref();
+    let escaped = regex::escape(s.trim_matches('*'));
+    match (s.starts_with('*'), s.ends_with('*')) {
+        (true, true) => escaped,
+        (true, false) => format!("{escaped}\\b"),
+        (false, true) => format!("\\b{escaped}"),
+        (false, false) => format!("\\b{escaped}\\b"),
+    }
+}
+
+fn is_valid_regex(re: impl AsRef<str>) -> bool {
+    let re = re.as_ref();
+    match Regex::new(re) {
+        Ok(_) => true,
+        Err(err) => {
+            log::warn!("Invalid automod regex '{re}': {err:?}");
+            false
+        }
+    }
+}
+
+fn compile_set(patterns: &[String]) -> Result<RegexSet, regex::Error> {
+    RegexSetBuilder::new(patterns)
+        .case_insensitive(true)
+        .build()
+}
+
+/// Result of matching a single keyword rule against some content.
+#[derive(Debug, Clone, Default)]
+pub struct RuleMatch {
+    /// Keywords and regexes (as written in the rule) that matched.
+    pub keywords: Vec<String>,
+    /// Allow list entries (as written in the rule) that matched.
+    pub allowed: Vec<String>,
+}
+
+impl RuleMatch {
+    /// Whether the rule would actually be enforced, i.e. something matched and nothing was allowed.
+    #[inline]
+    pub fn is_triggered(&self) -> bool {
+        !self.keywords.is_empty() && self.allowed.is_empty()
+    }
+}
+
+fn normalized(config: &Config, guild_id: GuildId, content: &str) -> Option<String> {
+    let normalize_config = config.discord.automod.get(&guild_id)?.normalize;
+    normalize_config
+        .is_enabled()
+        .then(|| normalize::normalize(content, normalize_config))
+}
+
+/// Matches all of the guild's keyword rules against `content` without enforcing anything.
+pub async fn test(
+    ctx: &Context,
+    guild_id: GuildId,
+    content: &str,
+) -> Result<Vec<(Rule, RuleMatch)>> {
+    let config = get_data::<ConfigKey>(ctx).await?;
+    let normalized = normalized(&config, guild_id, content);
+    let rules = get_rules(ctx, guild_id).await.map_err(|err| eyre!(err))?;
+    Ok(rules
+        .iter()
+        .map(|compiled| {
+            (
+                compiled.rule.clone(),
+                compiled.matches_normalized(content, normalized.as_deref()),
+            )
+        })
+        .collect())
+}
+
+/// Converts a Discord automod rule action into one the bot enforces itself, if it should.
+fn discord_action(config: &Config, action: &Action) -> Option<AutomodAction> {
+    match action {
+        Action::BlockMessage { custom_message } => Some(AutomodAction::Block {
+            message: custom_message.clone(),
+        }),
+        Action::Alert(channel_id) if config.discord.log_channels.contains(channel_id) => {
+            Some(AutomodAction::Alert)
+        }
+        _ => None,
+    }
+}
+
+/// Returns whether the message was deleted.
+async fn apply_actions(
+    ctx: &Context,
+    guild_id: GuildId,
+    message: &Message,
+    title: &str,
+    surface: &Surface<'_>,
+    actions: Vec<AutomodAction>,
+) -> bool {
+    let mut deleted = false;
+    for action in actions {
+        match action {
+            AutomodAction::Block { message: reply } => {
+                if let Err(err) = message
+                    .reply_ping(
+                        ctx,
+                        MessageBuilder::new()
+                            .push(reply.as_deref().unwrap_or("AutoMod blocked"))
+                            .build(),
+                    )
+                    .await
+                {
+                    log::warn!("Failed to yell at modmin for automod match: {err:?}");
+                }
+                match message.delete(ctx).await {
+                    Ok(()) => deleted = true,
+                    Err(err) => {
+                        log::error!("Failed to delete automod matched message: {err:?}");
+                    }
+                }
+            }
+            AutomodAction::Alert => {
+                if let Err(err) = log_channel::automod_enforced(
+                    ctx,
+                    guild_id,
+                    message,
+                    title,
+                    surface.kind,
+                    surface.text,
+                )
+                .await
+                {
+                    log::error!("Failed to log automod enforcement: {err:?}");
+                }
+            }
+        }
+    }
+    deleted
+}
+
+/// Enforces the rules on a new message, returning whether it was deleted.
+pub async fn enforce(ctx: &Context, message: &Message) -> Result<bool> {
+    // embeds sent with the message are enforced here already
+    let hash = embeds_hash(&surfaces::embed_surfaces(&message.embeds).collect_vec());
+    ENFORCED_EMBEDS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .cache_set(message.id, hash);
+    enforce_surfaces(ctx, message, surfaces::message_surfaces(message)).await
+}
+
+/// Hash of the embeds last enforced per message, as update events repeat them and the old
+/// message often isn't cached to compare with.
+static ENFORCED_EMBEDS: LazyLock<Mutex<LruTtlCache<MessageId, u64>>> = LazyLock::new(|| {
+    Mutex::new(
+        LruTtlCache::builder()
+            .max_size(10_000)
+            .ttl(Duration::from_secs(24 * 60 * 60))
+            .build()
+            .expect("valid cache size and TTL"),
+    )
+});
+
+fn embeds_hash(surfaces: &[Surface<'_>]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for surface in surfaces {
+        surface.kind.to_string().hash(&mut hasher);
+        surface.text.hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// Enforces rules on embeds added after the message was sent, e.g. link previews.
+/// Embeds that were already enforced for the message are skipped.
+pub async fn enforce_embeds(ctx: &Context, message: &Message) -> Result<bool> {
+    let surfaces = surfaces::embed_surfaces(&message.embeds).collect_vec();
+    let hash = embeds_hash(&surfaces);
+    {
+        let mut enforced = ENFORCED_EMBEDS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if enforced.cache_get(&message.id) == Some(&hash) {
+            return Ok(false);
+        }
+        enforced.cache_set(message.id, hash);
+    }
+    enforce_surfaces(ctx, message, surfaces).await
+}
+
+async fn enforce_surfaces(
+    ctx: &Context,
+    message: &Message,
+    surfaces: Vec<Surface<'_>>,
+) -> Result<bool> {
+    let config = get_data::<ConfigKey>(ctx).await?;
+    let Some(guild_id) = message.guild_id else {
+        return Ok(false);
+    };
+    let automod_config = config.discord.automod.get(&guild_id);
+    let enforce_discord_rules = config.discord.enforce_automods.contains(&guild_id);
+    if (!enforce_discord_rules && automod_config.is_none()) || surfaces.is_empty() {
+        return Ok(false);
+    }
+    let member = message.member(ctx).await?;
+    let normalized = surfaces
+        .iter()
+        .map(|surface| normalized(&config, guild_id, surface.text))
+        .collect_vec();
+
+    // the bot's own rules still apply when Discord's can't be fetched
+    let rules = if enforce_discord_rules {
+        get_rules(ctx, guild_id).await.unwrap_or_else(|err| {
+            log::error!("Unable to fetch automod rules of guild {guild_id}: {err}");
+            Arc::default()
+        })
+    } else {
+        Arc::default()
+    };
+    // one infraction per message, however many rules it broke
+    let mut matched: Vec<(&str, &Surface<'_>)> = vec![];
+    let mut deleted = false;
+    for compiled in rules.iter() {
+        let rule = &compiled.rule;
+        if rule.exempt_channels.contains(&message.channel_id)
+            || member
+                .roles
+                .iter()
+                .any(|role| rule.exempt_roles.contains(role))
+        {
+            continue;
+        }
+
+        let Some((surface, _)) = surfaces
+            .iter()
+            .zip(&normalized)
+            .find(|(surface, normalized)| {
+                compiled
+                    .matches_normalized(surface.text, normalized.as_deref())
+                    .is_triggered()
+            })
+        else {
+            continue;
+        };
+
+        let actions = rule
+            .actions
+            .iter()
+            .filter_map(|action| discord_action(&config, action))
+            .collect_vec();
+        deleted |= apply_actions(ctx, guild_id, message, &rule.name, surface, actions).await;
+        matched.push((&rule.name, surface));
+    }
+
+    for rule in automod_config.iter().flat_map(|automod| &automod.rules) {
+        if rule.is_exempt(message.channel_id, &member.roles) {
+            continue;
+        }
+
+        let Some(surface) = surfaces.iter().find(|surface| {
+            surface.kind.is_authored() && rule.kind.matches(message.channel_id, surface.text)
+        }) else {
+            continue;
+        };
+
+        deleted |= apply_actions(
+            ctx,
+            guild_id,
+            message,
+            &rule.name,
+            surface,
+            rule.actions.clone(),
+        )
+        .await;
+        matched.push((&rule.name, surface));
+    }
+
+    let Some((_, surface)) = matched.first() else {
+        return Ok(deleted);
+    };
+    let rule_names = matched.iter().map(|(name, _)| name).join(", ");
+    if let Err(err) = infractions::record(ctx, guild_id, message, &rule_names, surface).await {
+        log::error!("Failed to record automod infraction: {err:?}");
+    }
+    if let Some(automod_config) = automod_config
+        && let Err(err) = infractions::escalate(ctx, automod_config, &member).await
+    {
+        log::error!("Failed to escalate automod infractions: {err:?}");
+    }
+
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use serenity::all::Rule;
+
+    fn keyword_rule(
+        strings: &[&str],
+        regex_patterns: &[&str],
+        allow_list: &[&str],
+    ) -> super::CompiledRule {
+        let rule: Rule = serde_json::from_value(serde_json::json!({
+            "id": "1",
+            "guild_id": "2",
+            "name": "test",
+            "creator_id": "3",
+            "event_type": 1,
+            "trigger_type": 1,
+            "trigger_metadata": {
+                "keyword_filter": strings,
+                "regex_patterns": regex_patterns,
+                "allow_list": allow_list,
+            },
+            "actions": [],
+            "enabled": true,
+            "exempt_roles": [],
+            "exempt_channels": [],
+        }))
+        .unwrap();
+        super::CompiledRule::new(rule).unwrap()
+    }
+
+    #[test]
+    fn wildcards() {
+        let rule = keyword_rule(&["cat", "*dog", "bird*", "*fish*"], &[], &[]);
+        let matches = |content| rule.matches(content).keywords;
+
+        assert_eq!(matches("a cat"), vec!["cat"]);
+        assert_eq!(matches("a catalog"), Vec::<String>::new());
+        assert_eq!(matches("hotdog"), vec!["*dog"]);
+        assert_eq!(matches("doghouse"), Vec::<String>::new());
+        assert_eq!(matches("birdhouse"), vec!["bird*"]);
+        assert_eq!(matches("selfishness"), vec!["*fish*"]);
+        assert_eq!(matches("CAT"), vec!["cat"]);
+    }
+
+    #[test]
+    fn allow_list() {
+        let rule = keyword_rule(&[], &["d[i1]ck"], &["dickens"]);
+
+        let matched = rule.matches("d1ck");
+        assert!(matched.is_triggered());
+
+        let matched = rule.matches("Charles Dickens");
+        assert_eq!(matched.keywords, vec!["d[i1]ck"]);
+        assert_eq!(matched.allowed, vec!["dickens"]);
+        assert!(!matched.is_triggered());
+    }
+}
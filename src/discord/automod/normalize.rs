@@ -0,0 +1,211 @@
+use crate::config::NormalizeConfig;
+use unicode_normalization::{UnicodeNormalization, char::is_combining_mark};
+
+fn is_invisible(c: char) -> bool {
+    matches!(
+        c,
+        '\u{00AD}'
+            | '\u{034F}'
+            | '\u{061C}'
+            | '\u{115F}'
+            | '\u{1160}'
+            | '\u{17B4}'
+            | '\u{17B5}'
+            | '\u{180B}'..='\u{180F}'
+            | '\u{200B}'..='\u{200F}'
+            | '\u{202A}'..='\u{202E}'
+            | '\u{2060}'..='\u{206F}'
+            | '\u{3164}'
+            | '\u{FE00}'..='\u{FE0F}'
+            | '\u{FEFF}'
+            | '\u{FFA0}'
+            | '\u{E0000}'..='\u{E007F}'
+    )
+}
+
+/// Replaces Cyrillic and Greek lookalikes of Latin letters.
+fn confusable(c: char) -> char {
+    match c {
+        'а' | 'α' => 'a',
+        'в' | 'β' => 'b',
+        'с' => 'c',
+        'ԁ' => 'd',
+        'е' | 'ё' | 'ε' => 'e',
+        'һ' | 'н' => 'h',
+        'і' | 'ї' | 'ι' => 'i',
+        'ј' => 'j',
+        'к' | 'κ' => 'k',
+        'ӏ' => 'l',
+        'м' => 'm',
+        'η' => 'n',
+        'о' | 'ο' | 'σ' => 'o',
+        'р' | 'ρ' => 'p',
+        'ԛ' => 'q',
+        'ѕ' => 's',
+        'т' | 'τ' => 't',
+        'ц' | 'џ' | 'υ' => 'u',
+        'ѵ' | 'ν' => 'v',
+        'ԝ' | 'ш' => 'w',
+        'х' | 'χ' => 'x',
+        'у' => 'y',
+        'А' | 'Α' => 'A',
+        'В' | 'Β' => 'B',
+        'С' => 'C',
+        'Е' | 'Ё' | 'Ε' => 'E',
+        'Н' | 'Η' => 'H',
+        'І' | 'Ї' | 'Ι' => 'I',
+        'Ј' => 'J',
+        'К' | 'Κ' => 'K',
+        'М' | 'Μ' => 'M',
+        'Ν' => 'N',
+        'О' | 'Ο' => 'O',
+        'Р' | 'Ρ' => 'P',
+        'Ѕ' => 'S',
+        'Т' | 'Τ' => 'T',
+        'Х' | 'Χ' => 'X',
+        'У' | 'Ү' | 'Υ' => 'Y',
+        'Ζ' => 'Z',
+        c => c,
+    }
+}
+
+/// Replaces digits and symbols commonly used as letters.
+fn leetspeak(c: char) -> char {
+    match c {
+        '0' => 'o',
+        '1' | '!' | '|' => 'i',
+        '3' => 'e',
+        '4' | '@' => 'a',
+        '5' | '$' => 's',
+        '7' | '+' => 't',
+        '8' => 'b',
+        '9' => 'g',
+        c => c,
+    }
+}
+
+/// Applies the enabled normalization steps to `content`, to undo common automod evasion tricks.
+pub fn normalize(content: &str, config: NormalizeConfig) -> String {
+    let mut out = content.to_owned();
+    if config.invisible {
+        out.retain(|c| !is_invisible(c));
+    }
+    if config.compatibility {
+        out = out.nfkc().collect();
+    }
+    if config.combining {
+        out = out.nfd().filter(|c| !is_combining_mark(*c)).nfc().collect();
+    }
+    if config.confusables {
+        out = out.chars().map(confusable).collect();
+    }
+    if config.leetspeak {
+        out = out.chars().map(leetspeak).collect();
+    }
+    if config.compatibility && (config.confusables || config.leetspeak) {
+        // replaced characters might compose with combining marks following them
+        out = out.nfkc().collect();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::normalize;
+    use crate::config::NormalizeConfig;
+
+    pub(super) const ALL: NormalizeConfig = NormalizeConfig {
+        invisible: true,
+        compatibility: true,
+        combining: true,
+        confusables: true,
+        leetspeak: true,
+    };
+
+    #[test]
+    fn evasions() {
+        assert_eq!(normalize("h\u{200B}e\u{2060}llo\u{FEFF}", ALL), "hello");
+        assert_eq!(normalize("ｈｅｌｌｏ", ALL), "hello");
+        assert_eq!(normalize("h\u{0336}é\u{0301}l\u{0334}lö", ALL), "hello");
+        assert_eq!(normalize("һеllо", ALL), "hello");
+        assert_eq!(normalize("h3ll0", ALL), "hello");
+    }
+
+    #[test]
+    fn disabled_steps() {
+        let config = NormalizeConfig {
+            leetspeak: false,
+            ..ALL
+        };
+        assert_eq!(normalize("h3ll0", config), "h3ll0");
+        assert_eq!(normalize("h3ll0", NormalizeConfig::default()), "h3ll0");
+    }
+}
+
+#[cfg(test)]
+mod proptests {
+    use super::{is_invisible, normalize};
+    use crate::config::NormalizeConfig;
+    use proptest::prelude::*;
+    use unicode_normalization::char::is_combining_mark;
+
+    fn config() -> impl Strategy<Value = NormalizeConfig> {
+        any::<[bool; 5]>().prop_map(
+            |[invisible, compatibility, combining, confusables, leetspeak]| NormalizeConfig {
+                invisible,
+                compatibility,
+                combining,
+                confusables,
+                leetspeak,
+            },
+        )
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(1024))]
+
+        #[test]
+        fn disabled_is_identity(s in any::<String>()) {
+            assert_eq!(normalize(&s, NormalizeConfig::default()), s);
+        }
+
+        #[test]
+        fn idempotent(s in any::<String>(), config in config()) {
+            let once = normalize(&s, config);
+            assert_eq!(normalize(&once, config), once);
+        }
+
+        #[test]
+        fn ascii_letters_unchanged(s in "[a-zA-Z ]*", config in config()) {
+            assert_eq!(normalize(&s, config), s);
+        }
+
+        #[test]
+        fn no_invisible_left(s in any::<String>(), config in config()) {
+            let config = NormalizeConfig { invisible: true, ..config };
+            assert!(!normalize(&s, config).chars().any(is_invisible));
+        }
+
+        #[test]
+        fn no_combining_left(s in any::<String>(), config in config()) {
+            let config = NormalizeConfig { combining: true, ..config };
+            assert!(!normalize(&s, config).chars().any(is_combining_mark));
+        }
+
+        #[test]
+        fn obfuscated_ascii_recovered(s in "[a-z]{1,20}", zero_width in 0..4_usize) {
+            let obfuscated: String = s
+                .chars()
+                .flat_map(|c| {
+                    [
+                        char::from_u32(u32::from(c) + 0xFEE0).unwrap(),
+                        ['\u{200B}', '\u{200C}', '\u{200D}', '\u{2060}'][zero_width],
+                        '\u{0301}',
+                    ]
+                })
+                .collect();
+            let config = NormalizeConfig { leetspeak: false, ..super::tests::ALL };
+            assert_eq!(normalize(&obfuscated, config), s);
+        }
+    }
+}
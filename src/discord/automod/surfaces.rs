@@ -0,0 +1,118 @@
+use derive_more::Display;
+use serenity::all::{Attachment, Embed, Message, StickerItem};
+
+/// Where in a message a piece of text came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum SurfaceKind {
+    #[display("message")]
+    Content,
+    #[display("attachment filename")]
+    AttachmentFilename,
+    #[display("forwarded message")]
+    ForwardedContent,
+    #[display("forwarded attachment filename")]
+    ForwardedAttachmentFilename,
+    #[display("sticker name")]
+    StickerName,
+    #[display("poll question")]
+    PollQuestion,
+    #[display("poll answer")]
+    PollAnswer,
+    #[display("embed title")]
+    EmbedTitle,
+    #[display("embed description")]
+    EmbedDescription,
+}
+
+impl SurfaceKind {
+    /// Whether the text was written by the author, as opposed to being a name or metadata.
+    #[inline]
+    pub fn is_authored(self) -> bool {
+        matches!(self, Self::Content | Self::ForwardedContent)
+    }
+}
+
+/// A piece of text in a message that automod rules are matched against.
+#[derive(Debug, Clone)]
+pub struct Surface<'a> {
+    pub kind: SurfaceKind,
+    pub text: &'a str,
+}
+
+impl<'a> Surface<'a> {
+    #[inline]
+    fn new(kind: SurfaceKind, text: &'a str) -> Self {
+        Self { kind, text }
+    }
+}
+
+fn attachment_surfaces(
+    kind: SurfaceKind,
+    attachments: &[Attachment],
+) -> impl Iterator<Item = Surface<'_>> {
+    attachments
+        .iter()
+        .map(move |attach| Surface::new(kind, &attach.filename))
+}
+
+fn sticker_surfaces(stickers: &[StickerItem]) -> impl Iterator<Item = Surface<'_>> {
+    stickers
+        .iter()
+        .map(|sticker| Surface::new(SurfaceKind::StickerName, &sticker.name))
+}
+
+/// Titles and descriptions of the embeds, e.g. link previews.
+pub fn embed_surfaces(embeds: &[Embed]) -> impl Iterator<Item = Surface<'_>> {
+    embeds.iter().flat_map(|embed| {
+        embed
+            .title
+            .iter()
+            .map(|title| Surface::new(SurfaceKind::EmbedTitle, title))
+            .chain(
+                embed
+                    .description
+                    .iter()
+                    .map(|desc| Surface::new(SurfaceKind::EmbedDescription, desc)),
+            )
+    })
+}
+
+/// All the text in a message, starting with its content.
+pub fn message_surfaces(message: &Message) -> Vec<Surface<'_>> {
+    let mut surfaces = vec![Surface::new(SurfaceKind::Content, &message.content)];
+    surfaces.extend(attachment_surfaces(
+        SurfaceKind::AttachmentFilename,
+        &message.attachments,
+    ));
+    for snapshot in &message.message_snapshots {
+        surfaces.push(Surface::new(
+            SurfaceKind::ForwardedContent,
+            &snapshot.content,
+        ));
+        surfaces.extend(attachment_surfaces(
+            SurfaceKind::ForwardedAttachmentFilename,
+            &snapshot.attachments,
+        ));
+        surfaces.extend(sticker_surfaces(&snapshot.sticker_items));
+        surfaces.extend(embed_surfaces(&snapshot.embeds));
+    }
+    surfaces.extend(sticker_surfaces(&message.sticker_items));
+    if let Some(ref poll) = message.poll {
+        surfaces.extend(
+            poll.question
+                .text
+                .iter()
+                .map(|text| Surface::new(SurfaceKind::PollQuestion, text)),
+        );
+        surfaces.extend(poll.answers.iter().filter_map(|answer| {
+            answer
+                .poll_media
+                .text
+                .as_deref()
+                .map(|text| Surface::new(SurfaceKind::PollAnswer, text))
+        }));
+    }
+    surfaces.extend(embed_surfaces(&message.embeds));
+    surfaces.retain(|surface| !surface.text.is_empty());
+    surfaces
+}
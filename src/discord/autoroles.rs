@@ -0,0 +1,209 @@
+use super::{ConfigKey, DbKey, get_data, spawn_once};
+use bson::{doc, serde_helpers::datetime::FromChrono04DateTime};
+use chrono::{DateTime, TimeDelta, Utc};
+use color_eyre::eyre::Result;
+use futures::TryStreamExt;
+use log::{error, info};
+use mongodb::Collection;
+use serde::{Deserialize, Serialize};
+use serenity::{
+    all::{Context, GuildId, Member, RoleId, UserId},
+    http::StatusCode,
+    prelude::TypeMapKey,
+};
+use std::time::Duration;
+
+pub const COLLECTION_NAME: &str = "autoroles-pending";
+const WORKER_INTERVAL: Duration = Duration::from_secs(30);
+/// How long the worker waits before looking at a member who hasn't passed screening again,
+/// in case the update of them passing it was missed.
+const SCREENING_RECHECK: TimeDelta = TimeDelta::hours(1);
+/// How long after joining a member may still pass screening to get their delayed roles.
+const SCREENING_EXPIRY: TimeDelta = TimeDelta::days(7);
+
+#[derive(Debug)]
+struct WorkerKey;
+
+impl TypeMapKey for WorkerKey {
+    type Value = ();
+}
+
+/// A delayed role that hasn't been granted yet.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PendingRole {
+    guild_id: String,
+    user_id: String,
+    role_id: String,
+    /// Whether the member also has to pass membership screening first.
+    screening: bool,
+    #[serde(with = "FromChrono04DateTime")]
+    due: DateTime<Utc>,
+    /// Whether the role was due but the member hadn't passed screening yet.
+    #[serde(default)]
+    awaiting_screening: bool,
+}
+
+/// What became of a delayed role.
+enum Grant {
+    /// Granted, or no longer needed.
+    Done,
+    /// Waiting for the member to pass screening.
+    Pending,
+}
+
+async fn collection(ctx: &Context) -> Result<Collection<PendingRole>> {
+    Ok(get_data::<DbKey>(ctx).await?.collection(COLLECTION_NAME))
+}
+
+/// Grants the guild's autoroles and schedules its delayed roles.
+/// Runs after the stickies were applied, so restored roles aren't granted again.
+pub async fn on_join(ctx: &Context, member: &Member) -> Result<()> {
+    let config = get_data::<ConfigKey>(ctx).await?;
+    let Some(config) = config.discord.autoroles.get(&member.guild_id) else {
+        return Ok(());
+    };
+
+    let role_ids: Vec<RoleId> = config
+        .roles
+        .iter()
+        .filter(|role_id| !member.roles.contains(role_id))
+        .copied()
+        .collect();
+    if !role_ids.is_empty() {
+        info!("Granting autoroles {role_ids:?} to {}", member.user.tag());
+        member.add_roles(ctx, &role_ids).await?;
+    }
+
+    let collection = collection(ctx).await?;
+    let now = Utc::now();
+    for delayed in config
+        .delayed
+        .iter()
+        .filter(|delayed| !member.roles.contains(&delayed.role))
+    {
+        collection
+            .update_one(
+                doc! {
+                    "guild_id": member.guild_id.to_string(),
+                    "user_id": member.user.id.to_string(),
+                    "role_id": delayed.role.to_string(),
+                },
+                doc! {
+                    "$set": {
+                        "screening": delayed.screening,
+                        "due": now + TimeDelta::seconds(i64::try_from(delayed.delay)?),
+                        "awaiting_screening": false,
+                    },
+                },
+            )
+            .upsert(true)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Forgets the delayed roles of a member who left.
+pub async fn on_leave(ctx: &Context, guild_id: GuildId, user_id: UserId) -> Result<()> {
+    collection(ctx)
+        .await?
+        .delete_many(doc! {
+            "guild_id": guild_id.to_string(),
+            "user_id": user_id.to_string(),
+        })
+        .await?;
+    Ok(())
+}
+
+/// Grants a delayed role if the member is still there and has passed screening if needed.
+/// Looks the member up unless they're given.
+async fn grant(ctx: &Context, entry: &PendingRole, member: Option<&Member>) -> Result<Grant> {
+    let guild_id = GuildId::new(entry.guild_id.parse()?);
+    let user_id = UserId::new(entry.user_id.parse()?);
+    let role_id = RoleId::new(entry.role_id.parse()?);
+
+    let member = match member {
+        Some(member) => member.clone(),
+        // from the cache if possible
+        None => match guild_id.member(ctx, user_id).await {
+            Ok(member) => member,
+            Err(serenity::Error::Http(err)) if err.status_code() == Some(StatusCode::NOT_FOUND) => {
+                // left while we weren't looking
+                return Ok(Grant::Done);
+            }
+            Err(err) => return Err(err.into()),
+        },
+    };
+    if entry.screening && member.pending {
+        if member
+            .joined_at
+            .is_some_and(|joined_at| Utc::now() - *joined_at > SCREENING_EXPIRY)
+        {
+            info!(
+                "Giving up on delayed role {role_id} for {}, who never passed screening",
+                member.user.tag()
+            );
+            return Ok(Grant::Done);
+        }
+        return Ok(Grant::Pending);
+    }
+    if !member.roles.contains(&role_id) {
+        info!("Granting delayed role {role_id} to {}", member.user.tag());
+        member.add_role(ctx, role_id).await?;
+    }
+    Ok(Grant::Done)
+}
+
+/// Grants the delayed roles that are due, optionally only those of a member who just passed
+/// screening.
+pub async fn grant_due(ctx: &Context, member: Option<&Member>) -> Result<()> {
+    let collection = collection(ctx).await?;
+    let now = Utc::now();
+    let mut filter = doc! { "due": { "$lte": now } };
+    if let Some(member) = member {
+        filter = doc! {
+            "guild_id": member.guild_id.to_string(),
+            "user_id": member.user.id.to_string(),
+            "$or": [filter, { "awaiting_screening": true }],
+        };
+    }
+
+    let due: Vec<PendingRole> = collection.find(filter).await?.try_collect().await?;
+    for entry in due {
+        let entry_filter = doc! {
+            "guild_id": &entry.guild_id,
+            "user_id": &entry.user_id,
+            "role_id": &entry.role_id,
+        };
+        match grant(ctx, &entry, member).await {
+            Ok(Grant::Done) => {
+                collection.delete_one(entry_filter).await?;
+            }
+            Ok(Grant::Pending) => {
+                // passing screening comes as a member update, so there's no need to poll often
+                collection
+                    .update_one(
+                        entry_filter,
+                        doc! {
+                            "$set": {
+                                "due": now + SCREENING_RECHECK,
+                                "awaiting_screening": true,
+                            },
+                        },
+                    )
+                    .await?;
+            }
+            Err(err) => error!("Unable to grant delayed role {entry:?}: {err:?}"),
+        }
+    }
+    Ok(())
+}
+
+/// Periodically grants the delayed roles that are due, including those from before a restart.
+pub async fn spawn_worker(ctx: &Context) {
+    spawn_once::<WorkerKey, _, _>(ctx, WORKER_INTERVAL, |ctx| async move {
+        if let Err(err) = grant_due(&ctx, None).await {
+            error!("Unable to grant delayed roles: {err:?}");
+        }
+    })
+    .await;
+}
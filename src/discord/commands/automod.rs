@@ -0,0 +1,169 @@
+use super::{
+    super::limits::{EMBED_DESC_LENGTH, REPLY_LENGTH},
+    is_moderator,
+};
+use crate::{
+    Result,
+    config::AutomodAction,
+    discord::{Context, automod, automod::surfaces::SurfaceKind},
+    util::ellipsis_string,
+};
+use color_eyre::eyre::OptionExt;
+use itertools::Itertools;
+use poise::{CreateReply, command};
+use serenity::all::{
+    CreateEmbed, CreateEmbedAuthor, CreateEmbedFooter, MessageBuilder, User, automod::Action,
+};
+
+fn describe_action(action: &Action) -> String {
+    match action {
+        Action::BlockMessage {
+            custom_message: Some(custom_message),
+        } => format!("block (\"{custom_message}\")"),
+        Action::BlockMessage {
+            custom_message: None,
+        } => String::from("block"),
+        Action::Alert(channel_id) => format!("alert in <#{channel_id}>"),
+        Action::Timeout(duration) => format!("timeout for {} seconds", duration.as_secs()),
+        action => format!("{action:?}"),
+    }
+}
+
+fn describe_bot_action(action: &AutomodAction) -> String {
+    match action {
+        AutomodAction::Block {
+            message: Some(message),
+        } => format!("block (\"{message}\")"),
+        AutomodAction::Block { message: None } => String::from("block"),
+        AutomodAction::Alert => String::from("alert"),
+    }
+}
+
+/// Test how this server's automod rules would match some text, without enforcing anything
+#[command(
+    prefix_command,
+    category = "Moderation",
+    rename = "automodtest",
+    guild_only,
+    check = "is_moderator",
+    invoke_on_edit,
+    track_deletion
+)]
+pub async fn automodtest(ctx: Context<'_>, #[rest] text: String) -> Result<()> {
+    let guild_id = ctx.guild_id().ok_or_eyre("no guild ID")?;
+    let results = automod::test(ctx.serenity_context(), guild_id, &text).await?;
+
+    let mut response = MessageBuilder::new();
+    for (rule, matched) in results {
+        if matched.keywords.is_empty() {
+            continue;
+        }
+        response
+            .push_bold_safe(&rule.name)
+            .push(": matched ")
+            .push_line(
+                matched
+                    .keywords
+                    .iter()
+                    .map(|keyword| format!("`{}`", keyword.replace('`', "'")))
+                    .join(", "),
+            );
+        if matched.is_triggered() {
+            response
+                .push("\u{2192} ")
+                .push_line(if rule.actions.is_empty() {
+                    String::from("no actions")
+                } else {
+                    rule.actions.iter().map(describe_action).join(", ")
+                });
+        } else {
+            response.push("\u{2192} allowed by ").push_line(
+                matched
+                    .allowed
+                    .iter()
+                    .map(|allowed| format!("`{}`", allowed.replace('`', "'")))
+                    .join(", "),
+            );
+        }
+    }
+
+    for rule in ctx
+        .data()
+        .config
+        .discord
+        .automod
+        .get(&guild_id)
+        .iter()
+        .flat_map(|automod| &automod.rules)
+        .filter(|rule| rule.kind.matches(ctx.channel_id(), &text))
+    {
+        response
+            .push_bold_safe(&rule.name)
+            .push_line(" (bot rule): matched")
+            .push("\u{2192} ")
+            .push_line(if rule.actions.is_empty() {
+                String::from("no actions")
+            } else {
+                rule.actions.iter().map(describe_bot_action).join(", ")
+            });
+    }
+
+    let response = response.build();
+    ctx.reply(if response.is_empty() {
+        String::from("No AutoMod rules matched.")
+    } else {
+        ellipsis_string(response, REPLY_LENGTH)
+    })
+    .await?;
+    Ok(())
+}
+
+/// List a user's most recent automod infractions
+#[command(
+    prefix_command,
+    category = "Moderation",
+    guild_only,
+    check = "is_moderator",
+    invoke_on_edit,
+    track_deletion
+)]
+pub async fn infractions(ctx: Context<'_>, user: User) -> Result<()> {
+    let guild_id = ctx.guild_id().ok_or_eyre("no guild ID")?;
+    let (total, recent) =
+        automod::infractions::list(ctx.serenity_context(), guild_id, user.id, 10).await?;
+
+    let mut description = MessageBuilder::new();
+    for infraction in &recent {
+        description
+            .push(format!(
+                "<t:{}:f> <#{}> ",
+                infraction.time.timestamp(),
+                infraction.channel_id
+            ))
+            .push_bold_safe(&infraction.rule);
+        if !infraction.surface.is_empty() && infraction.surface != SurfaceKind::Content.to_string()
+        {
+            description.push_italic(format!(" in {}", infraction.surface));
+        }
+        description
+            .push_line("")
+            .push_line_safe(ellipsis_string(&infraction.content, 100));
+    }
+
+    ctx.send(
+        CreateReply::default().embed(
+            CreateEmbed::new()
+                .author(CreateEmbedAuthor::new(user.tag()).icon_url(user.face()))
+                .description(if recent.is_empty() {
+                    String::from("No infractions")
+                } else {
+                    ellipsis_string(description.build(), EMBED_DESC_LENGTH)
+                })
+                .footer(CreateEmbedFooter::new(format!(
+                    "{total} infractions in total"
+                ))),
+        ),
+    )
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,141 @@
+use super::{super::guild_settings, is_admin};
+use crate::{Result, discord::Context};
+use color_eyre::eyre::OptionExt;
+use itertools::Itertools;
+use poise::command;
+use serenity::all::{GuildChannel, Mentionable, MessageBuilder};
+
+const MAX_PREFIX_LENGTH: usize = 16;
+
+/// Configure the bot for this server
+#[command(
+    prefix_command,
+    category = "Admin",
+    guild_only,
+    check = "is_admin",
+    subcommands("prefix", "channels"),
+    subcommand_required
+)]
+#[allow(clippy::unused_async)]
+pub async fn config(_ctx: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+/// Show or change the command prefix, "reset" goes back to the default
+#[command(prefix_command, category = "Admin", guild_only, check = "is_admin")]
+pub async fn prefix(ctx: Context<'_>, prefix: Option<String>) -> Result<()> {
+    let guild_id = ctx.guild_id().ok_or_eyre("no guild ID")?;
+    let serenity_ctx = ctx.serenity_context();
+    match prefix.as_deref().map(str::trim) {
+        None => {}
+        Some("reset") => guild_settings::set_prefix(serenity_ctx, guild_id, None).await?,
+        Some(prefix) if prefix.is_empty() || prefix.chars().count() > MAX_PREFIX_LENGTH => {
+            ctx.reply(format!(
+                "The prefix has to be between 1 and {MAX_PREFIX_LENGTH} characters."
+            ))
+            .await?;
+            return Ok(());
+        }
+        Some(prefix) => guild_settings::set_prefix(serenity_ctx, guild_id, Some(prefix)).await?,
+    }
+    let current = guild_settings::prefix(serenity_ctx, &ctx.data().config, Some(guild_id)).await;
+    ctx.reply(
+        MessageBuilder::new()
+            .push("The prefix is ")
+            .push_mono_safe(current)
+            .build(),
+    )
+    .await?;
+    Ok(())
+}
+
+/// List the channels commands are allowed in
+#[command(
+    prefix_command,
+    category = "Admin",
+    guild_only,
+    check = "is_admin",
+    subcommands("add", "remove")
+)]
+pub async fn channels(ctx: Context<'_>) -> Result<()> {
+    let channels = guild_settings::command_channels(
+        ctx.serenity_context(),
+        &ctx.data().config,
+        ctx.guild_id(),
+    )
+    .await;
+    ctx.reply(if channels.is_empty() {
+        String::from("Commands aren't allowed in any channel.")
+    } else {
+        format!(
+            "Commands are allowed in {}",
+            channels.iter().map(Mentionable::mention).join(", ")
+        )
+    })
+    .await?;
+    Ok(())
+}
+
+/// Allow commands in a channel
+#[command(prefix_command, category = "Admin", guild_only, check = "is_admin")]
+pub async fn add(ctx: Context<'_>, channel: GuildChannel) -> Result<()> {
+    let guild_id = ctx.guild_id().ok_or_eyre("no guild ID")?;
+    if channel.guild_id != guild_id {
+        ctx.reply("That channel isn't in this server.").await?;
+        return Ok(());
+    }
+    let mut channels = guild_settings::command_channels(
+        ctx.serenity_context(),
+        &ctx.data().config,
+        Some(guild_id),
+    )
+    .await;
+    if !channels.insert(channel.id) {
+        ctx.reply(format!(
+            "Commands are already allowed in {}.",
+            channel.mention()
+        ))
+        .await?;
+        return Ok(());
+    }
+    guild_settings::set_command_channels(ctx.serenity_context(), guild_id, &channels).await?;
+    ctx.reply(format!(
+        "Commands are now allowed in {}.",
+        channel.mention()
+    ))
+    .await?;
+    Ok(())
+}
+
+/// Stop allowing commands in a channel
+#[command(prefix_command, category = "Admin", guild_only, check = "is_admin")]
+pub async fn remove(ctx: Context<'_>, channel: GuildChannel) -> Result<()> {
+    let guild_id = ctx.guild_id().ok_or_eyre("no guild ID")?;
+    let mut channels = guild_settings::command_channels(
+        ctx.serenity_context(),
+        &ctx.data().config,
+        Some(guild_id),
+    )
+    .await;
+    if !channels.remove(&channel.id) {
+        ctx.reply(format!(
+            "Commands already aren't allowed in {}.",
+            channel.mention()
+        ))
+        .await?;
+        return Ok(());
+    }
+    if channels.is_empty() {
+        // nobody could run commands anymore, including this one to undo it
+        ctx.reply("Commands have to be allowed in at least one channel.")
+            .await?;
+        return Ok(());
+    }
+    guild_settings::set_command_channels(ctx.serenity_context(), guild_id, &channels).await?;
+    ctx.reply(format!(
+        "Commands are no longer allowed in {}.",
+        channel.mention()
+    ))
+    .await?;
+    Ok(())
+}
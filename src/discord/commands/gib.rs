@@ -1,11 +1,13 @@
 use super::super::{
-    ConfigKey, DbKey, get_data, get_data_or_insert_with, limits::EMBED_FIELD_VALUE_LENGTH,
+    ConfigKey, DbKey, get_data, get_data_or_insert_with,
+    limits::{AUTOCOMPLETE_CHOICE_LENGTH, AUTOCOMPLETE_CHOICES, EMBED_FIELD_VALUE_LENGTH},
 };
 use crate::{
     Result,
     discord::Context,
     util::{ellipsis_string, separate_thousands_unsigned},
 };
+use cached::cached;
 use chrono::{DateTime, Utc};
 use color_eyre::eyre::eyre;
 use futures::StreamExt;
@@ -19,7 +21,7 @@ use serenity::{
     all::{CreateEmbed, CreateEmbedFooter},
     prelude::TypeMapKey,
 };
-use std::time::Duration;
+use std::{cmp::Reverse, time::Duration};
 
 #[derive(Debug, Clone, Deserialize)]
 struct SearchResponse {
@@ -43,6 +45,17 @@ struct Representations {
     tall: String,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+struct TagsResponse {
+    tags: Vec<Tag>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Tag {
+    name: String,
+    images: u64,
+}
+
 #[derive(Debug)]
 struct ClientKey;
 
@@ -52,12 +65,9 @@ impl TypeMapKey for ClientKey {
 
 const COLLECTION_NAME: &str = "gib-seen";
 
-pub async fn derpibooru_search(ctx: &Context<'_>, query: &str) -> Result<Option<(Image, usize)>> {
+async fn client(ctx: &Context<'_>) -> Result<Client> {
     let config = get_data::<ConfigKey>(ctx.serenity_context()).await?;
-    let collection = get_data::<DbKey>(ctx.serenity_context())
-        .await?
-        .collection::<Document>(COLLECTION_NAME);
-    let client = get_data_or_insert_with::<ClientKey, _>(ctx.serenity_context(), || {
+    get_data_or_insert_with::<ClientKey, _>(ctx.serenity_context(), || {
         Client::builder()
             .user_agent(config.gib.user_agent.to_string())
             .connect_timeout(Duration::from_secs(10))
@@ -66,7 +76,15 @@ pub async fn derpibooru_search(ctx: &Context<'_>, query: &str) -> Result<Option<
             .map_err(|err| format!("Unable to create reqwest::Client: {err:?}"))
     })
     .await
-    .map_err(|err| eyre!(err))?;
+    .map_err(|err| eyre!(err))
+}
+
+pub async fn derpibooru_search(ctx: &Context<'_>, query: &str) -> Result<Option<(Image, usize)>> {
+    let config = get_data::<ConfigKey>(ctx.serenity_context()).await?;
+    let collection = get_data::<DbKey>(ctx.serenity_context())
+        .await?
+        .collection::<Document>(COLLECTION_NAME);
+    let client = client(ctx).await?;
 
     let response: SearchResponse = client
         .get(Url::parse_with_params(
@@ -154,15 +172,78 @@ pub async fn derpibooru_embed(ctx: &Context<'_>, image: &Image, total: usize) ->
     Ok(())
 }
 
+/// Tags starting with `partial`, most used first. Cached, as autocomplete asks on every keystroke.
+#[cached(ttl = 3600, key = "String", convert = "{ partial.to_owned() }")]
+async fn search_tags(ctx: &Context<'_>, partial: &str) -> Result<Vec<String>, String> {
+    fetch_tags(ctx, partial)
+        .await
+        .map_err(|err| format!("{err:?}"))
+}
+
+async fn fetch_tags(ctx: &Context<'_>, partial: &str) -> Result<Vec<String>> {
+    let config = get_data::<ConfigKey>(ctx.serenity_context()).await?;
+    let Some(ref endpoint) = config.gib.tags_endpoint else {
+        return Ok(vec![]);
+    };
+    let mut response: TagsResponse = client(ctx)
+        .await?
+        .get(Url::parse_with_params(
+            endpoint.as_ref(),
+            &[("q", format!("{partial}*"))],
+        )?)
+        .send()
+        .await?
+        .json()
+        .await?;
+    response
+        .tags
+        .sort_unstable_by_key(|tag| Reverse(tag.images));
+    Ok(response.tags.into_iter().map(|tag| tag.name).collect())
+}
+
+/// Completes the last of the comma separated tags typed so far.
+async fn autocomplete_tags(ctx: Context<'_>, partial: &str) -> Vec<String> {
+    let (done, last) = partial.rsplit_once(',').unwrap_or(("", partial));
+    let last = last.trim();
+    if last.is_empty() {
+        return vec![];
+    }
+    match search_tags(&ctx, last).await {
+        Ok(tags) => tags
+            .into_iter()
+            .map(|tag| {
+                if done.trim().is_empty() {
+                    tag
+                } else {
+                    format!("{}, {tag}", done.trim_end())
+                }
+            })
+            .filter(|query| query.chars().count() <= AUTOCOMPLETE_CHOICE_LENGTH)
+            .take(AUTOCOMPLETE_CHOICES)
+            .collect(),
+        Err(err) => {
+            log::warn!("Unable to autocomplete gib tags: {err:?}");
+            vec![]
+        }
+    }
+}
+
 /// Gib pics matching the given tags from Derpibooru
 #[command(
     prefix_command,
+    slash_command,
     category = "Horse",
     aliases("give", "derpi", "derpibooru"),
     invoke_on_edit,
     track_deletion
 )]
-pub async fn gib(ctx: Context<'_>, #[rest] query: Option<String>) -> Result<()> {
+pub async fn gib(
+    ctx: Context<'_>,
+    #[description = "Tags, separated by commas"]
+    #[autocomplete = "autocomplete_tags"]
+    #[rest]
+    query: Option<String>,
+) -> Result<()> {
     let query = query.as_ref().map_or("*", |s| s.trim());
 
     if let Some((image, total)) = derpibooru_search(&ctx, query).await? {
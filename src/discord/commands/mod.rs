@@ -1,14 +1,106 @@
-use crate::Result;
+use crate::{Result, config::CommandPolicy};
 use poise::{Command, command, samples::HelpConfiguration};
+use serenity::all::{Permissions, RoleId};
+use std::collections::HashMap;
 
+mod automod;
+mod config;
 mod gib;
+mod policy;
 mod ranks;
 mod roll;
+#[cfg(feature = "starboard")]
+mod stars;
+mod stickies;
+mod volatile;
 
-use super::{Context, PoiseData};
+use super::{Context, PoiseData, guild_settings};
+
+/// The policy of the command in the current guild, the default if it has none.
+fn guild_policy(ctx: Context<'_>, qualified_name: &str) -> CommandPolicy {
+    let default = CommandPolicy::default();
+    let policy = policy::find(&ctx.data().config.discord.command_policies, qualified_name)
+        .unwrap_or(&default);
+    match ctx.guild() {
+        Some(guild) => policy::in_guild(policy, |channel_id| {
+            guild.channels.contains_key(&channel_id)
+        }),
+        None => policy.clone(),
+    }
+}
+
+/// Checked before every command: the author mustn't be blocked, and the command's policy has to
+/// allow it where it's used.
+pub(super) async fn command_check(ctx: Context<'_>) -> Result<bool> {
+    let config = &ctx.data().config;
+    if config.discord.blocked_users.contains(&ctx.author().id) {
+        return Ok(false);
+    }
+    let policy = guild_policy(ctx, &ctx.command().qualified_name);
+
+    let nsfw = if policy.nsfw {
+        match ctx.guild_channel().await {
+            // threads inherit being NSFW from their parent
+            Some(channel) if channel.thread_metadata.is_some() => channel
+                .parent_id
+                .and_then(|parent_id| {
+                    ctx.guild()
+                        .and_then(|guild| guild.channels.get(&parent_id).map(|parent| parent.nsfw))
+                })
+                .unwrap_or(false),
+            Some(channel) => channel.nsfw,
+            None => false,
+        }
+    } else {
+        false
+    };
+    let roles = if policy.roles.is_empty() {
+        vec![]
+    } else {
+        ctx.author_member()
+            .await
+            .map(|member| member.roles.clone())
+            .unwrap_or_default()
+    };
+    let command_channels =
+        guild_settings::command_channels(ctx.serenity_context(), config, ctx.guild_id()).await;
+
+    Ok(policy::allows(
+        &policy,
+        &command_channels,
+        &policy::Invocation {
+            channel_id: ctx.channel_id(),
+            dm: ctx.guild_id().is_none(),
+            nsfw,
+            roles: &roles,
+        },
+    ))
+}
+
+/// Whether the author is a bot owner or has the permissions in the guild.
+async fn has_permissions(ctx: Context<'_>, permissions: Permissions) -> bool {
+    if ctx.framework().options().owners.contains(&ctx.author().id) {
+        return true;
+    }
+    let Some(member) = ctx.author_member().await else {
+        return false;
+    };
+    ctx.guild()
+        .is_some_and(|guild| guild.member_permissions(&member).contains(permissions))
+}
+
+/// Check for moderation commands: allows bot owners and members who can manage messages.
+async fn is_moderator(ctx: Context<'_>) -> Result<bool> {
+    Ok(has_permissions(ctx, Permissions::MANAGE_MESSAGES).await)
+}
+
+/// Check for configuration commands: allows bot owners and members who can manage the server.
+async fn is_admin(ctx: Context<'_>) -> Result<bool> {
+    Ok(has_permissions(ctx, Permissions::MANAGE_GUILD).await)
+}
 
 /// pong
-#[command(prefix_command, category = "Misc", track_deletion)]
+#[command(prefix_command, slash_command, category = "Misc", track_deletion)]
 async fn ping(ctx: Context<'_>) -> Result<()> {
     ctx.reply("Pong!").await?;
     Ok(())
@@ -16,7 +108,7 @@ async fn ping(ctx: Context<'_>) -> Result<()> {
 
 /// Toggle showing "thinking" when generating LLM responses to you (if supported by the current model)
 #[cfg(feature = "openai")]
-#[command(prefix_command, category = "Misc", track_deletion)]
+#[command(prefix_command, slash_command, category = "Misc", track_deletion)]
 async fn think(ctx: Context<'_>) -> Result<()> {
     use crate::discord::{DbKey, get_data};
     use bson::{Document, doc};
@@ -68,15 +160,48 @@ async fn think(ctx: Context<'_>) -> Result<()> {
 }
 
 /// List commands, or show help for a specific command
-#[command(prefix_command, category = "Misc", invoke_on_edit, track_deletion)]
+#[command(
+    prefix_command,
+    slash_command,
+    category = "Misc",
+    invoke_on_edit,
+    track_deletion
+)]
 async fn help(
     ctx: Context<'_>,
     #[description = "Specific command to show help about"] command: Option<String>,
 ) -> Result<()> {
+    let role_names: HashMap<RoleId, String> = ctx
+        .guild()
+        .map(|guild| {
+            guild
+                .roles
+                .iter()
+                .map(|(id, role)| (*id, role.name.clone()))
+                .collect()
+        })
+        .unwrap_or_default();
+    let describe = |policy: &CommandPolicy| {
+        policy::describe(policy, |role_id| {
+            role_names
+                .get(&role_id)
+                .cloned()
+                .unwrap_or_else(|| role_id.to_string())
+        })
+    };
+    let policy = match command.as_deref() {
+        None => format!("By default: {}", describe(&CommandPolicy::default())),
+        Some(name) => {
+            poise::find_command(&ctx.framework().options().commands, name, true, &mut vec![])
+                .map(|(command, _, _)| describe(&guild_policy(ctx, &command.qualified_name)))
+                .unwrap_or_default()
+        }
+    };
     poise::builtins::help(
         ctx,
         command.as_deref(),
         HelpConfiguration {
+            extra_text_at_bottom: &policy,
             ..Default::default()
         },
     )
@@ -86,12 +211,19 @@ async fn help(
 
 pub(super) fn get_all() -> Vec<Command<PoiseData, crate::Error>> {
     vec![
+        automod::automodtest(),
+        automod::infractions(),
+        config::config(),
         gib::gib(),
         ranks::join(),
         ranks::leave(),
         ranks::rank(),
         ranks::ranks(),
         roll::roll(),
+        #[cfg(feature = "starboard")]
+        stars::stars(),
+        stickies::stickies(),
+        volatile::volatile(),
         ping(),
         help(),
         #[cfg(feature = "openai")]
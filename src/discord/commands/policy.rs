@@ -0,0 +1,205 @@
+use crate::config::CommandPolicy;
+use serenity::all::{ChannelId, RoleId};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write,
+};
+
+/// Where a command is being used, and by whom.
+#[derive(Debug, Clone)]
+pub struct Invocation<'a> {
+    pub channel_id: ChannelId,
+    pub dm: bool,
+    pub nsfw: bool,
+    pub roles: &'a [RoleId],
+}
+
+/// The policy of the command, or of its closest parent that has one.
+pub fn find<'a>(
+    policies: &'a HashMap<String, CommandPolicy>,
+    qualified_name: &str,
+) -> Option<&'a CommandPolicy> {
+    let mut name = qualified_name;
+    loop {
+        if let Some(policy) = policies.get(name) {
+            return Some(policy);
+        }
+        name = name.rsplit_once(' ')?.0;
+    }
+}
+
+/// The policy as it applies in a guild, which may not have all of its channels.
+/// Without any of its channels, the command goes back to the guild's command channels.
+pub fn in_guild(policy: &CommandPolicy, has_channel: impl Fn(ChannelId) -> bool) -> CommandPolicy {
+    let mut policy = policy.clone();
+    policy
+        .channels
+        .retain(|channel_id| has_channel(*channel_id));
+    policy
+        .deny_channels
+        .retain(|channel_id| has_channel(*channel_id));
+    policy
+}
+
+pub fn allows(
+    policy: &CommandPolicy,
+    command_channels: &HashSet<ChannelId>,
+    invocation: &Invocation<'_>,
+) -> bool {
+    if invocation.dm {
+        return policy.dm;
+    }
+    let channels = if policy.channels.is_empty() {
+        command_channels
+    } else {
+        &policy.channels
+    };
+    (policy.anywhere || channels.contains(&invocation.channel_id))
+        && !policy.deny_channels.contains(&invocation.channel_id)
+        && (!policy.nsfw || invocation.nsfw)
+        && (policy.roles.is_empty()
+            || invocation
+                .roles
+                .iter()
+                .any(|role| policy.roles.contains(role)))
+}
+
+/// Explains the policy for the help of its command, naming roles with `role_name`.
+pub fn describe(policy: &CommandPolicy, role_name: impl Fn(RoleId) -> String) -> String {
+    let mentions = |channels: &HashSet<ChannelId>| {
+        channels
+            .iter()
+            .map(|channel| format!("<#{channel}>"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    let mut out = String::new();
+    if policy.anywhere {
+        out.push_str("Works in every channel");
+    } else if policy.channels.is_empty() {
+        out.push_str("Works in the command channels");
+    } else {
+        let _ = write!(out, "Works in {}", mentions(&policy.channels));
+    }
+    if !policy.deny_channels.is_empty() {
+        let _ = write!(out, " except {}", mentions(&policy.deny_channels));
+    }
+    if policy.nsfw {
+        out.push_str(", if they're NSFW");
+    }
+    out.push('.');
+    if !policy.roles.is_empty() {
+        let mut names: Vec<String> = policy.roles.iter().copied().map(role_name).collect();
+        names.sort_unstable();
+        let _ = write!(out, " Needs one of the roles {}.", names.join(", "));
+    }
+    if policy.dm {
+        out.push_str(" Also works in DMs.");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Invocation, allows, describe, find, in_guild};
+    use crate::config::CommandPolicy;
+    use maplit::{hashmap, hashset};
+    use serenity::all::{ChannelId, RoleId};
+
+    #[test]
+    fn lookup() {
+        let policies = hashmap! {
+            String::from("stars") => CommandPolicy { dm: true, ..Default::default() },
+            String::from("stars top") => CommandPolicy::default(),
+        };
+        assert!(!find(&policies, "stars top").unwrap().dm);
+        assert!(find(&policies, "stars me").unwrap().dm);
+        assert!(find(&policies, "stars").unwrap().dm);
+        assert!(find(&policies, "gib").is_none());
+    }
+
+    #[test]
+    fn policies() {
+        let command_channels = hashset! { ChannelId::new(1) };
+        let here = |channel_id, nsfw, roles| Invocation {
+            channel_id: ChannelId::new(channel_id),
+            dm: false,
+            nsfw,
+            roles,
+        };
+        let dm = Invocation {
+            channel_id: ChannelId::new(9),
+            dm: true,
+            nsfw: false,
+            roles: &[],
+        };
+
+        let default = CommandPolicy::default();
+        assert!(allows(&default, &command_channels, &here(1, false, &[])));
+        assert!(!allows(&default, &command_channels, &here(2, false, &[])));
+        assert!(!allows(&default, &command_channels, &dm));
+
+        let anywhere = CommandPolicy {
+            anywhere: true,
+            deny_channels: hashset! { ChannelId::new(3) },
+            dm: true,
+            ..Default::default()
+        };
+        assert!(allows(&anywhere, &command_channels, &here(2, false, &[])));
+        assert!(!allows(&anywhere, &command_channels, &here(3, false, &[])));
+        assert!(allows(&anywhere, &command_channels, &dm));
+
+        let nsfw = CommandPolicy {
+            channels: hashset! { ChannelId::new(4) },
+            nsfw: true,
+            roles: hashset! { RoleId::new(5) },
+            ..Default::default()
+        };
+        let roles = [RoleId::new(6), RoleId::new(5)];
+        assert!(allows(&nsfw, &command_channels, &here(4, true, &roles)));
+        assert!(!allows(&nsfw, &command_channels, &here(1, true, &roles)));
+        assert!(!allows(&nsfw, &command_channels, &here(4, false, &roles)));
+        assert!(!allows(
+            &nsfw,
+            &command_channels,
+            &here(4, true, &roles[..1])
+        ));
+    }
+
+    #[test]
+    fn guild_channels() {
+        let policy = CommandPolicy {
+            channels: hashset! { ChannelId::new(1), ChannelId::new(2) },
+            deny_channels: hashset! { ChannelId::new(3) },
+            ..Default::default()
+        };
+        let here = in_guild(&policy, |channel_id| channel_id.get() == 2);
+        assert_eq!(here.channels, hashset! { ChannelId::new(2) });
+        assert!(here.deny_channels.is_empty());
+        assert!(in_guild(&policy, |_| false).channels.is_empty());
+    }
+
+    #[test]
+    fn descriptions() {
+        let name = |role: RoleId| format!("role{role}");
+        assert_eq!(
+            describe(&CommandPolicy::default(), name),
+            "Works in the command channels."
+        );
+        assert_eq!(
+            describe(
+                &CommandPolicy {
+                    anywhere: true,
+                    deny_channels: hashset! { ChannelId::new(3) },
+                    nsfw: true,
+                    roles: hashset! { RoleId::new(5) },
+                    dm: true,
+                    ..Default::default()
+                },
+                name
+            ),
+            "Works in every channel except <#3>, if they're NSFW. Needs one of the roles role5. Also works in DMs."
+        );
+    }
+}
@@ -1,8 +1,8 @@
 #![allow(clippy::non_canonical_partial_ord_impl)] // derivative
 
 use super::super::{
-    ConfigKey, get_data,
-    limits::{EMBED_DESC_LENGTH, REPLY_LENGTH},
+    ConfigKey, get_data, guild_settings,
+    limits::{AUTOCOMPLETE_CHOICES, EMBED_DESC_LENGTH, REPLY_LENGTH},
 };
 use crate::{discord::Context, util::ellipsis_string};
 use color_eyre::eyre::{OptionExt, Result, eyre};
@@ -180,9 +180,36 @@ async fn handle_joinleave(
     Ok(())
 }
 
+/// Suggests the ranks whose name contains what was typed so far.
+async fn autocomplete_rank(ctx: Context<'_>, partial: &str) -> Vec<String> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return vec![];
+    };
+    let Ok(ranks) = Ranks::from_guild(ctx.serenity_context(), guild_id).await else {
+        return vec![];
+    };
+    let search = partial.trim().to_lowercase();
+    ranks
+        .names()
+        .filter(|name| name.to_lowercase().contains(&search))
+        .take(AUTOCOMPLETE_CHOICES)
+        .collect()
+}
+
 /// Join a rank
-#[command(prefix_command, category = "Ranks", aliases("gain"))]
-pub async fn join(ctx: Context<'_>, ranks: Vec<String>) -> Result<()> {
+#[command(
+    prefix_command,
+    slash_command,
+    category = "Ranks",
+    guild_only,
+    aliases("gain")
+)]
+pub async fn join(
+    ctx: Context<'_>,
+    #[description = "Rank to join"]
+    #[autocomplete = "autocomplete_rank"]
+    ranks: Vec<String>,
+) -> Result<()> {
     handle_joinleave(
         &ctx,
         ranks,
@@ -199,8 +226,13 @@ pub async fn join(ctx: Context<'_>, ranks: Vec<String>) -> Result<()> {
 }
 
 /// Leave a rank
-#[command(prefix_command, category = "Ranks")]
-pub async fn leave(ctx: Context<'_>, ranks: Vec<String>) -> Result<()> {
+#[command(prefix_command, slash_command, category = "Ranks", guild_only)]
+pub async fn leave(
+    ctx: Context<'_>,
+    #[description = "Rank to leave"]
+    #[autocomplete = "autocomplete_rank"]
+    ranks: Vec<String>,
+) -> Result<()> {
     handle_joinleave(
         &ctx,
         ranks,
@@ -237,7 +269,7 @@ pub async fn rank(ctx: Context<'_>, ranks: Vec<String>) -> Result<()> {
 }
 
 /// List all available ranks, and which ones you currently have
-#[command(prefix_command, category = "Ranks")]
+#[command(prefix_command, slash_command, category = "Ranks", guild_only)]
 pub async fn ranks(ctx: Context<'_>) -> Result<()> {
     let ranks = Ranks::from_guild(
         ctx.serenity_context(),
@@ -272,10 +304,8 @@ pub async fn ranks(ctx: Context<'_>) -> Result<()> {
         String::from_utf8(tw.into_inner()?)?
     };
 
-    let prefix = get_data::<ConfigKey>(ctx.serenity_context())
-        .await?
-        .discord
-        .command_prefix;
+    let prefix =
+        guild_settings::prefix(ctx.serenity_context(), &ctx.data().config, ctx.guild_id()).await;
     ctx.send(
         CreateReply::default().embed(
             CreateEmbed::new()
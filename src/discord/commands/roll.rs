@@ -11,12 +11,18 @@ use serenity::utils::MessageBuilder;
 /// Example: 1d6 + 2d20 - 3
 #[command(
     prefix_command,
+    slash_command,
     category = "Misc",
     aliases("calc", "calculate", "calculator"),
     invoke_on_edit,
     track_deletion
 )]
-pub async fn roll(ctx: Context<'_>, #[rest] expression: String) -> Result<()> {
+pub async fn roll(
+    ctx: Context<'_>,
+    #[description = "Dice and math, like 1d6 + 2d20 - 3"]
+    #[rest]
+    expression: String,
+) -> Result<()> {
     let expression = expression.trim();
 
     let input = regex_replace_all!(
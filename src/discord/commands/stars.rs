@@ -0,0 +1,233 @@
+use super::super::{
+    limits::{EMBED_DESC_LENGTH, EMBED_FIELD_VALUE_LENGTH},
+    starboard,
+};
+use crate::{Result, config::BoardConfig, discord::Context, util::ellipsis_string};
+use color_eyre::eyre::{OptionExt, eyre};
+use poise::{CreateReply, command};
+use serenity::all::{
+    ChannelId, CreateAllowedMentions, CreateEmbed, CreateEmbedAuthor, MessageId, StatusCode, User,
+};
+use std::fmt::Write;
+
+/// Starred messages to pick from, in case some were deleted.
+const RANDOM_ATTEMPTS: u8 = 5;
+
+fn message_link(guild_id: &str, starred: &starboard::StarredMessage) -> String {
+    starred.channel_id.as_ref().map_or_else(
+        || String::from("unknown message"),
+        |channel_id| {
+            format!(
+                "https://discord.com/channels/{guild_id}/{channel_id}/{}",
+                starred.message_id
+            )
+        },
+    )
+}
+
+/// The board named by the user, or the guild's first one.
+fn resolve_board(ctx: Context<'_>, name: Option<&str>) -> Result<BoardConfig> {
+    let guild = ctx.guild().ok_or_eyre("Guild not in cache")?;
+    starboard::find_board(&ctx.data().config, &guild, name)
+        .cloned()
+        .ok_or_else(|| eyre!("No such starboard"))
+}
+
+fn list_messages(emoji: &str, guild_id: &str, messages: &[starboard::StarredMessage]) -> String {
+    let mut out = String::new();
+    for (i, starred) in messages.iter().enumerate() {
+        let _ = write!(
+            out,
+            "{}. {emoji} {} {}",
+            i + 1,
+            starred.count,
+            message_link(guild_id, starred)
+        );
+        if let Some(ref author_id) = starred.author_id {
+            let _ = write!(out, " by <@{author_id}>");
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Starboard statistics
+#[command(
+    prefix_command,
+    category = "Starboard",
+    guild_only,
+    subcommands("top", "me", "random"),
+    subcommand_required
+)]
+#[allow(clippy::unused_async)]
+pub async fn stars(_ctx: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+/// Show the most starred members and messages
+#[command(
+    prefix_command,
+    category = "Starboard",
+    guild_only,
+    invoke_on_edit,
+    track_deletion
+)]
+pub async fn top(ctx: Context<'_>, board: Option<String>) -> Result<()> {
+    let guild_id = ctx.guild_id().ok_or_eyre("no guild ID")?;
+    let board = resolve_board(ctx, board.as_deref())?;
+    let emoji = &*board.emoji;
+    let authors =
+        starboard::top_authors(ctx.serenity_context(), guild_id, &board, None, 10).await?;
+    let messages =
+        starboard::top_messages(ctx.serenity_context(), guild_id, &board, None, 10).await?;
+
+    let mut members = String::new();
+    for (i, author) in authors.iter().enumerate() {
+        let _ = writeln!(
+            members,
+            "{}. <@{}> {emoji} {} ({} messages)",
+            i + 1,
+            author.author_id,
+            author.stars,
+            author.messages
+        );
+    }
+
+    ctx.send(
+        CreateReply::default()
+            .allowed_mentions(CreateAllowedMentions::new())
+            .embed(
+                CreateEmbed::new()
+                    .title(format!("Most starred on {}", board.name))
+                    .field(
+                        "Members",
+                        if members.is_empty() {
+                            String::from("Nobody yet")
+                        } else {
+                            ellipsis_string(members, EMBED_FIELD_VALUE_LENGTH)
+                        },
+                        false,
+                    )
+                    .field(
+                        "Messages",
+                        if messages.is_empty() {
+                            String::from("Nothing yet")
+                        } else {
+                            ellipsis_string(
+                                list_messages(emoji, &guild_id.to_string(), &messages),
+                                EMBED_FIELD_VALUE_LENGTH,
+                            )
+                        },
+                        false,
+                    ),
+            ),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Show your stars, or someone else's
+#[command(
+    prefix_command,
+    category = "Starboard",
+    guild_only,
+    invoke_on_edit,
+    track_deletion
+)]
+pub async fn me(ctx: Context<'_>, user: Option<User>, board: Option<String>) -> Result<()> {
+    let guild_id = ctx.guild_id().ok_or_eyre("no guild ID")?;
+    let user = user.as_ref().unwrap_or_else(|| ctx.author());
+    let board = resolve_board(ctx, board.as_deref())?;
+    let emoji = &*board.emoji;
+    let totals = starboard::top_authors(ctx.serenity_context(), guild_id, &board, Some(user.id), 1)
+        .await?
+        .pop();
+    let messages =
+        starboard::top_messages(ctx.serenity_context(), guild_id, &board, Some(user.id), 5).await?;
+
+    let description = totals.map_or_else(
+        || String::from("No starred messages"),
+        |totals| {
+            format!(
+                "{emoji} {} on {} messages\n\n{}",
+                totals.stars,
+                totals.messages,
+                list_messages(emoji, &guild_id.to_string(), &messages)
+            )
+        },
+    );
+
+    ctx.send(
+        CreateReply::default()
+            .allowed_mentions(CreateAllowedMentions::new())
+            .embed(
+                CreateEmbed::new()
+                    .author(CreateEmbedAuthor::new(user.tag()).icon_url(user.face()))
+                    .description(ellipsis_string(description, EMBED_DESC_LENGTH)),
+            ),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Resurface a random starred message
+#[command(prefix_command, category = "Starboard", guild_only, track_deletion)]
+pub async fn random(ctx: Context<'_>, board: Option<String>) -> Result<()> {
+    let guild_id = ctx.guild_id().ok_or_eyre("no guild ID")?;
+    let board = resolve_board(ctx, board.as_deref())?;
+    let sample =
+        starboard::random(ctx.serenity_context(), guild_id, &board, RANDOM_ATTEMPTS).await?;
+    if sample.is_empty() {
+        ctx.reply("Nothing has been starred yet.").await?;
+        return Ok(());
+    }
+    let mut found = None;
+    for starred in sample {
+        let Some(ref channel_id) = starred.channel_id else {
+            continue;
+        };
+        match ctx
+            .http()
+            .get_message(
+                ChannelId::new(channel_id.parse()?),
+                MessageId::new(starred.message_id.parse()?),
+            )
+            .await
+        {
+            Ok(message) => {
+                found = Some((starred, message));
+                break;
+            }
+            // deleted since, try another one
+            Err(serenity::Error::Http(err)) if err.status_code() == Some(StatusCode::NOT_FOUND) => {
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+    let Some((starred, message)) = found else {
+        ctx.reply("Couldn't find a starred message that still exists.")
+            .await?;
+        return Ok(());
+    };
+    let guild = ctx.guild().ok_or_eyre("Guild not in cache")?.clone();
+
+    let mut reply = CreateReply::default()
+        .allowed_mentions(CreateAllowedMentions::new())
+        .content(starboard::header(
+            &board.emoji,
+            starred.count,
+            &message,
+            guild_id,
+        ))
+        .embed(
+            starboard::create_embed_from_message(ctx.serenity_context(), &guild, &message).await?,
+        );
+    for attach in starboard::download_attachments(&message).await? {
+        reply = reply.attachment(attach);
+    }
+    for embed in &message.embeds {
+        reply = reply.embed(embed.clone().into());
+    }
+    ctx.send(reply).await?;
+    Ok(())
+}
@@ -0,0 +1,104 @@
+use super::{super::sticky_roles, is_moderator};
+use crate::{Result, discord::Context};
+use chrono::Utc;
+use color_eyre::eyre::OptionExt;
+use itertools::Itertools;
+use poise::{CreateReply, command};
+use serenity::all::{CreateEmbed, CreateEmbedAuthor, Mentionable, MessageBuilder, Role, User};
+
+/// Show the roles that would be restored to a user when they rejoin
+#[command(
+    prefix_command,
+    category = "Moderation",
+    guild_only,
+    check = "is_moderator",
+    subcommands("clear", "remove"),
+    invoke_on_edit,
+    track_deletion
+)]
+pub async fn stickies(ctx: Context<'_>, user: User) -> Result<()> {
+    let guild_id = ctx.guild_id().ok_or_eyre("no guild ID")?;
+    let Some(stickies) =
+        sticky_roles::get_stickies(ctx.serenity_context(), guild_id, user.id).await?
+    else {
+        ctx.reply("No stickies stored for that user.").await?;
+        return Ok(());
+    };
+
+    let mut description = MessageBuilder::new();
+    if stickies.role_ids.is_empty() {
+        description.push_line("No roles");
+    } else {
+        description.push_line(stickies.role_ids.iter().map(Mentionable::mention).join(" "));
+    }
+    if let Some(ref nick) = stickies.nick {
+        description.push("Nickname: ").push_line_safe(nick);
+    }
+    if let Some(until) = stickies.timeout_until.filter(|until| *until > Utc::now()) {
+        description.push_line(format!("Timed out until <t:{}:f>", until.timestamp()));
+    }
+    if let Some(left_at) = stickies.left_at {
+        description.push(format!("Left <t:{}:R>", left_at.timestamp()));
+        let sticky_config = ctx.data().config.discord.sticky_roles.get(&guild_id);
+        if stickies.is_expired(sticky_config) {
+            description.push(", expired");
+        }
+        description.push_line("");
+    }
+
+    ctx.send(
+        CreateReply::default().embed(
+            CreateEmbed::new()
+                .author(CreateEmbedAuthor::new(user.tag()).icon_url(user.face()))
+                .description(description.build()),
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Forget all stored roles and nickname of a user
+#[command(
+    prefix_command,
+    category = "Moderation",
+    guild_only,
+    check = "is_moderator",
+    track_deletion
+)]
+pub async fn clear(ctx: Context<'_>, user: User) -> Result<()> {
+    let guild_id = ctx.guild_id().ok_or_eyre("no guild ID")?;
+    if sticky_roles::clear_stickies(ctx.serenity_context(), guild_id, user.id).await? {
+        ctx.reply(format!("Cleared stickies of {}.", user.tag()))
+            .await?;
+    } else {
+        ctx.reply("No stickies stored for that user.").await?;
+    }
+    Ok(())
+}
+
+/// Stop a role from being restored to a user
+#[command(
+    prefix_command,
+    category = "Moderation",
+    guild_only,
+    check = "is_moderator",
+    track_deletion
+)]
+pub async fn remove(ctx: Context<'_>, user: User, #[rest] role: Role) -> Result<()> {
+    let guild_id = ctx.guild_id().ok_or_eyre("no guild ID")?;
+    if sticky_roles::remove_sticky(ctx.serenity_context(), guild_id, user.id, role.id).await? {
+        ctx.reply(
+            MessageBuilder::new()
+                .push("Removed ")
+                .push_safe(&role.name)
+                .push(" from the stickies of ")
+                .push_safe(user.tag())
+                .push(".")
+                .build(),
+        )
+        .await?;
+    } else {
+        ctx.reply("That role isn't stored for that user.").await?;
+    }
+    Ok(())
+}
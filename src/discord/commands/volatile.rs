@@ -0,0 +1,167 @@
+use super::{
+    super::{limits::EMBED_DESC_LENGTH, volatiles},
+    is_moderator,
+};
+use crate::{Result, discord::Context, util::ellipsis_string};
+use color_eyre::eyre::OptionExt;
+use poise::{CreateReply, command};
+use serenity::all::{
+    CreateEmbed, CreateEmbedAuthor, GuildChannel, Mentionable, MessageBuilder, User,
+};
+
+fn describe_window(entry: &volatiles::Entry) -> String {
+    let posts = if entry.count == 1 {
+        String::from("1 post")
+    } else {
+        format!("{} posts", entry.count)
+    };
+    match entry.window_end {
+        _ if entry.has_reset() => format!("{posts} <t:{}:R>, reset", entry.time.timestamp()),
+        Some(end) => format!(
+            "{posts} <t:{}:R>, resets <t:{}:R>",
+            entry.time.timestamp(),
+            end.timestamp()
+        ),
+        None => format!("{posts} <t:{}:R>", entry.time.timestamp()),
+    }
+}
+
+/// Tells the user off unless the channel is a volatile channel of this guild.
+async fn check_channel(ctx: Context<'_>, channel: &GuildChannel) -> Result<bool> {
+    let guild_id = ctx.guild_id().ok_or_eyre("no guild ID")?;
+    if channel.guild_id == guild_id
+        && volatiles::is_volatile(
+            ctx.serenity_context(),
+            &ctx.data().config,
+            guild_id,
+            channel.id,
+        )
+    {
+        return Ok(true);
+    }
+    ctx.reply(format!("{} isn't a volatile channel.", channel.mention()))
+        .await?;
+    Ok(false)
+}
+
+/// Manage who has posted in volatile channels
+#[command(
+    prefix_command,
+    category = "Moderation",
+    guild_only,
+    check = "is_moderator",
+    subcommands("list", "reset", "status"),
+    subcommand_required
+)]
+#[allow(clippy::unused_async)]
+pub async fn volatile(_ctx: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+/// List everyone who has posted in a volatile channel
+#[command(
+    prefix_command,
+    category = "Moderation",
+    guild_only,
+    check = "is_moderator",
+    invoke_on_edit,
+    track_deletion
+)]
+pub async fn list(ctx: Context<'_>, channel: GuildChannel) -> Result<()> {
+    if !check_channel(ctx, &channel).await? {
+        return Ok(());
+    }
+    let entries = volatiles::list(ctx.serenity_context(), channel.id).await?;
+
+    let mut description = MessageBuilder::new();
+    for entry in &entries {
+        description
+            .mention(&entry.user_id)
+            .push(" (")
+            .push_safe(&entry.name)
+            .push("): ")
+            .push_line(describe_window(entry));
+    }
+
+    ctx.send(
+        CreateReply::default().embed(
+            CreateEmbed::new()
+                .title(format!("Volatile posts in #{}", channel.name))
+                .description(if entries.is_empty() {
+                    String::from("Nobody has posted")
+                } else {
+                    ellipsis_string(description.build(), EMBED_DESC_LENGTH)
+                }),
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Let a user post in a volatile channel again, removing its role
+#[command(
+    prefix_command,
+    category = "Moderation",
+    guild_only,
+    check = "is_moderator",
+    track_deletion
+)]
+pub async fn reset(ctx: Context<'_>, user: User, channel: GuildChannel) -> Result<()> {
+    let guild_id = ctx.guild_id().ok_or_eyre("no guild ID")?;
+    if !check_channel(ctx, &channel).await? {
+        return Ok(());
+    }
+    let had_record =
+        volatiles::reset(ctx.serenity_context(), guild_id, channel.id, user.id).await?;
+    ctx.reply(
+        MessageBuilder::new()
+            .push(if had_record {
+                "Reset "
+            } else {
+                "Nothing recorded for "
+            })
+            .push_safe(user.tag())
+            .push(" in ")
+            .mention(&channel)
+            .push(", they can post again.")
+            .build(),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Show a user's posts in volatile channels
+#[command(
+    prefix_command,
+    category = "Moderation",
+    guild_only,
+    check = "is_moderator",
+    invoke_on_edit,
+    track_deletion
+)]
+pub async fn status(ctx: Context<'_>, user: User) -> Result<()> {
+    let guild_id = ctx.guild_id().ok_or_eyre("no guild ID")?;
+    let entries = volatiles::status(ctx.serenity_context(), guild_id, user.id).await?;
+
+    let mut description = MessageBuilder::new();
+    for entry in &entries {
+        description
+            .push(entry.channel_id.mention().to_string())
+            .push(": ")
+            .push_line(describe_window(entry));
+    }
+
+    ctx.send(
+        CreateReply::default().embed(
+            CreateEmbed::new()
+                .author(CreateEmbedAuthor::new(user.tag()).icon_url(user.face()))
+                .description(if entries.is_empty() {
+                    String::from("No volatile posts")
+                } else {
+                    ellipsis_string(description.build(), EMBED_DESC_LENGTH)
+                }),
+        ),
+    )
+    .await?;
+    Ok(())
+}
@@ -1,19 +1,19 @@
 use super::{
-    ActivityKey, automod, limits::ACTIVITY_LENGTH, log_channel, stats::update_stats, sticky_roles,
-    volatiles,
+    ActivityKey, automod, autoroles, limits::ACTIVITY_LENGTH, log_channel, quotas,
+    stats::update_stats, sticky_roles, volatiles,
 };
 use crate::util::ellipsis_string;
 use log::error;
 #[cfg(feature = "starboard")]
 use serenity::all::Reaction;
 use serenity::{
-    all::{ActivityData, GuildMemberUpdateEvent},
+    all::{ActivityData, GuildMemberUpdateEvent, MessageUpdateEvent, Rule},
     async_trait,
     client::{Context, EventHandler},
     model::{
         channel::Message,
         gateway::Ready,
-        guild::Member,
+        guild::{Guild, Member},
         id::{ChannelId, GuildId, MessageId},
         user::User,
     },
@@ -25,6 +25,16 @@ pub struct Handler;
 #[async_trait]
 impl EventHandler for Handler {
     async fn ready(&self, ctx: Context, _ready: Ready) {
+        if let Err(err) = sticky_roles::spawn_reconciler(&ctx).await {
+            error!("Unable to spawn sticky role reconciler: {err:?}");
+        }
+        autoroles::spawn_worker(&ctx).await;
+        volatiles::spawn_sweeper(&ctx).await;
+        #[cfg(feature = "starboard")]
+        if let Err(err) = super::starboard::spawn_worker(&ctx).await {
+            error!("Unable to spawn starboard worker: {err:?}");
+        }
+
         if let Some(activity) = {
             let data = ctx.data.read().await;
             data.get::<ActivityKey>()
@@ -34,6 +44,13 @@ impl EventHandler for Handler {
         }
     }
 
+    #[cfg(feature = "starboard")]
+    async fn cache_ready(&self, ctx: Context, guilds: Vec<GuildId>) {
+        if let Err(err) = super::starboard::catch_up(&ctx, &guilds).await {
+            error!("Error in starboard catch up: {err:?}");
+        }
+    }
+
     async fn message(&self, ctx: Context, message: Message) {
         if let Err(err) = update_stats(&ctx, &message).await {
             error!("Error in update_stats for normal_message: {err:?}");
@@ -43,8 +60,17 @@ impl EventHandler for Handler {
             return;
         }
 
-        if let Err(err) = automod::enforce(&ctx, &message).await {
-            error!("Error enforcing automod: {err:?}");
+        // later stages don't apply to messages that were removed already
+        match automod::enforce(&ctx, &message).await {
+            Ok(true) => return,
+            Ok(false) => {}
+            Err(err) => error!("Error enforcing automod: {err:?}"),
+        }
+
+        match quotas::enforce(&ctx, &message).await {
+            Ok(true) => return,
+            Ok(false) => {}
+            Err(err) => error!("Error enforcing quotas: {err:?}"),
         }
 
         if let Err(err) = volatiles::enforce(&ctx, &message).await {
@@ -53,9 +79,8 @@ impl EventHandler for Handler {
 
         #[cfg(feature = "openai")]
         if let Ok(config) = super::get_data::<super::ConfigKey>(&ctx).await {
-            if config
-                .discord
-                .command_channels
+            if super::guild_settings::command_channels(&ctx, &config, message.guild_id)
+                .await
                 .contains(&message.channel_id)
                 && matches!(message.mentions_me(&ctx).await, Ok(true))
             {
@@ -79,6 +104,43 @@ impl EventHandler for Handler {
         }
     }
 
+    async fn message_update(
+        &self,
+        ctx: Context,
+        old: Option<Message>,
+        new: Option<Message>,
+        event: MessageUpdateEvent,
+    ) {
+        // link previews get added with an update after the message was sent
+        let Some(ref embeds) = event.embeds else {
+            return;
+        };
+        if embeds.is_empty() || old.is_some_and(|old| &old.embeds == embeds) {
+            return;
+        }
+        let message = match new {
+            Some(message) => message,
+            None => match event.channel_id.message(&ctx, event.id).await {
+                Ok(mut message) => {
+                    // only messages from the gateway come with their guild
+                    message.guild_id = event.guild_id;
+                    message
+                }
+                Err(err) => {
+                    error!("Unable to fetch updated message: {err:?}");
+                    return;
+                }
+            },
+        };
+        if message.author.bot {
+            return;
+        }
+
+        if let Err(err) = automod::enforce_embeds(&ctx, &message).await {
+            error!("Error enforcing automod on embeds: {err:?}");
+        }
+    }
+
     async fn message_delete(
         &self,
         ctx: Context,
@@ -86,6 +148,11 @@ impl EventHandler for Handler {
         message_id: MessageId,
         guild_id: Option<GuildId>,
     ) {
+        #[cfg(feature = "starboard")]
+        if let Err(err) = super::starboard::on_message_delete(&ctx, message_id).await {
+            error!("Error in starboard message_delete: {err:?}");
+        }
+
         if let Some(guild_id) = guild_id {
             if let Some(message) = ctx
                 .cache
@@ -108,6 +175,13 @@ impl EventHandler for Handler {
         messages_ids: Vec<MessageId>,
         guild_id: Option<GuildId>,
     ) {
+        #[cfg(feature = "starboard")]
+        for message_id in &messages_ids {
+            if let Err(err) = super::starboard::on_message_delete(&ctx, *message_id).await {
+                error!("Error in starboard message_delete_bulk: {err:?}");
+            }
+        }
+
         if let Some(guild_id) = guild_id {
             for message_id in messages_ids {
                 if let Some(message) = ctx
@@ -125,12 +199,27 @@ impl EventHandler for Handler {
         }
     }
 
+    async fn guild_create(&self, ctx: Context, guild: Guild, _is_new: Option<bool>) {
+        // fires for every guild on startup, so members without updates get a record too
+        if let Err(err) = sticky_roles::snapshot(&ctx, guild.id).await {
+            error!("Unable to snapshot stickies: {err:?}");
+        }
+    }
+
     async fn guild_member_addition(&self, ctx: Context, mut member: Member) {
-        if let Err(err) = log_channel::member_added(&ctx, member.guild_id, &member.user).await {
-            error!("Unable to log member addition: {err:?}");
+        let restored = sticky_roles::apply_stickies(&ctx, &mut member)
+            .await
+            .unwrap_or_else(|err| {
+                error!("Unable to apply stickies: {err:?}");
+                sticky_roles::Restored::default()
+            });
+        if let Err(err) = autoroles::on_join(&ctx, &member).await {
+            error!("Unable to grant autoroles: {err:?}");
         }
-        if let Err(err) = sticky_roles::apply_stickies(&ctx, &mut member).await {
-            error!("Unable to apply stickies: {err:?}");
+        if let Err(err) =
+            log_channel::member_added(&ctx, member.guild_id, &member.user, &restored).await
+        {
+            error!("Unable to log member addition: {err:?}");
         }
     }
 
@@ -144,6 +233,12 @@ impl EventHandler for Handler {
         if let Err(err) = log_channel::member_removed(&ctx, guild_id, &user).await {
             error!("Unable to log member removal: {err:?}");
         }
+        if let Err(err) = sticky_roles::mark_left(&ctx, guild_id, user.id).await {
+            error!("Unable to mark stickies as left: {err:?}");
+        }
+        if let Err(err) = autoroles::on_leave(&ctx, guild_id, user.id).await {
+            error!("Unable to forget delayed roles: {err:?}");
+        }
     }
 
     async fn guild_member_update(
@@ -164,6 +259,24 @@ impl EventHandler for Handler {
         if let Err(err) = sticky_roles::save_stickies(&ctx, &new_member).await {
             error!("Unable to save stickies: {err:?}");
         }
+        // passing membership screening comes as an update
+        if !new_member.pending && old_member.is_none_or(|old_member| old_member.pending) {
+            if let Err(err) = autoroles::grant_due(&ctx, Some(&new_member)).await {
+                error!("Unable to grant delayed roles: {err:?}");
+            }
+        }
+    }
+
+    async fn auto_moderation_rule_create(&self, _ctx: Context, rule: Rule) {
+        automod::invalidate_rules(rule.guild_id).await;
+    }
+
+    async fn auto_moderation_rule_update(&self, _ctx: Context, rule: Rule) {
+        automod::invalidate_rules(rule.guild_id).await;
+    }
+
+    async fn auto_moderation_rule_delete(&self, _ctx: Context, rule: Rule) {
+        automod::invalidate_rules(rule.guild_id).await;
     }
 
     #[cfg(feature = "starboard")]
@@ -0,0 +1,120 @@
+use super::{DbKey, get_data};
+use crate::config::Config;
+use bson::doc;
+use cached::{Cached, cached};
+use color_eyre::eyre::{Result, eyre};
+use mongodb::Collection;
+use serde::{Deserialize, Serialize};
+use serenity::all::{ChannelId, Context, GuildId};
+use std::{collections::HashSet, sync::Arc};
+
+pub const COLLECTION_NAME: &str = "guild-settings";
+
+/// Per-guild overrides of the global [`crate::config::DiscordConfig`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GuildSettings {
+    #[serde(default)]
+    pub prefix: Option<String>,
+    #[serde(default)]
+    pub command_channels: Option<HashSet<ChannelId>>,
+}
+
+async fn collection(ctx: &Context) -> Result<Collection<GuildSettings>> {
+    Ok(get_data::<DbKey>(ctx).await?.collection(COLLECTION_NAME))
+}
+
+// Only changed through `update`, which invalidates the cache.
+#[cached(sync_writes = "default", key = "GuildId", convert = "{guild_id}")]
+async fn get(ctx: &Context, guild_id: GuildId) -> Result<Arc<GuildSettings>, String> {
+    let settings = collection(ctx)
+        .await
+        .map_err(|err| format!("{err:?}"))?
+        .find_one(doc! { "guild_id": guild_id.to_string() })
+        .await
+        .map_err(|err| format!("{:?}", eyre!(err)))?;
+    Ok(Arc::new(settings.unwrap_or_default()))
+}
+
+/// The guild's settings, or the defaults if they can't be loaded.
+async fn get_or_default(ctx: &Context, guild_id: Option<GuildId>) -> Arc<GuildSettings> {
+    let Some(guild_id) = guild_id else {
+        return Arc::default();
+    };
+    get(ctx, guild_id).await.unwrap_or_else(|err| {
+        log::error!("Unable to load settings of guild {guild_id}: {err}");
+        Arc::default()
+    })
+}
+
+async fn update(ctx: &Context, guild_id: GuildId, update: bson::Document) -> Result<()> {
+    collection(ctx)
+        .await?
+        .update_one(doc! { "guild_id": guild_id.to_string() }, update)
+        .upsert(true)
+        .await?;
+    GET.write().await.cache_remove(&guild_id);
+    Ok(())
+}
+
+/// The command prefix of the guild, falling back to the global one.
+pub async fn prefix(ctx: &Context, config: &Config, guild_id: Option<GuildId>) -> String {
+    get_or_default(ctx, guild_id)
+        .await
+        .prefix
+        .clone()
+        .unwrap_or_else(|| config.discord.command_prefix.to_string())
+}
+
+/// The channels the guild allows commands in, falling back to the global ones in the guild.
+pub async fn command_channels(
+    ctx: &Context,
+    config: &Config,
+    guild_id: Option<GuildId>,
+) -> HashSet<ChannelId> {
+    get_or_default(ctx, guild_id)
+        .await
+        .command_channels
+        .clone()
+        .unwrap_or_else(|| {
+            let guild = guild_id.and_then(|guild_id| ctx.cache.guild(guild_id));
+            config
+                .discord
+                .command_channels
+                .iter()
+                .filter(|channel_id| {
+                    guild
+                        .as_ref()
+                        .is_none_or(|guild| guild.channels.contains_key(channel_id))
+                })
+                .copied()
+                .collect()
+        })
+}
+
+/// Overrides the guild's command prefix, or goes back to the global one.
+pub async fn set_prefix(ctx: &Context, guild_id: GuildId, prefix: Option<&str>) -> Result<()> {
+    update(
+        ctx,
+        guild_id,
+        prefix.map_or_else(
+            || doc! { "$unset": { "prefix": "" } },
+            |prefix| doc! { "$set": { "prefix": prefix } },
+        ),
+    )
+    .await
+}
+
+/// Overrides the channels the guild allows commands in.
+pub async fn set_command_channels(
+    ctx: &Context,
+    guild_id: GuildId,
+    channels: &HashSet<ChannelId>,
+) -> Result<()> {
+    let channels: Vec<String> = channels.iter().map(ToString::to_string).collect();
+    update(
+        ctx,
+        guild_id,
+        doc! { "$set": { "command_channels": channels } },
+    )
+    .await
+}
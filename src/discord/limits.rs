@@ -15,3 +15,6 @@ pub const MESSAGE_LENGTH: usize = 2000 - MARGIN;
 pub const NICK_LENGTH: usize = 32;
 pub const ACTIVITY_LENGTH: usize = 128 - MARGIN;
 pub const REPLY_LENGTH: usize = MESSAGE_LENGTH - NICK_LENGTH;
+
+pub const AUTOCOMPLETE_CHOICES: usize = 25;
+pub const AUTOCOMPLETE_CHOICE_LENGTH: usize = 100;
@@ -1,8 +1,12 @@
-use super::{ConfigKey, get_data, limits::EMBED_DESC_LENGTH};
+use super::{
+    ConfigKey, automod::surfaces::SurfaceKind, get_data, limits::EMBED_DESC_LENGTH,
+    sticky_roles::Restored,
+};
 use crate::util::ellipsis_string;
 use color_eyre::eyre::{Error, Result};
+use itertools::Itertools;
 use serenity::{
-    all::{Colour, CreateEmbedAuthor, CreateEmbedFooter, CreateMessage},
+    all::{Colour, CreateEmbedAuthor, CreateEmbedFooter, CreateMessage, Mentionable},
     builder::CreateEmbed,
     client::Context,
     model::{
@@ -39,8 +43,23 @@ pub async fn automod_enforced(
     guild_id: GuildId,
     message: &Message,
     title: impl AsRef<str>,
+    surface: SurfaceKind,
+    text: impl AsRef<str>,
 ) -> Result<()> {
     send_log(ctx, guild_id, || {
+        let mut heading = MessageBuilder::new();
+        heading
+            .push("Message sent by ")
+            .mention(&message.author)
+            .push(" on ")
+            .mention(&message.channel_id)
+            .push(" matched AutoMod rule \"")
+            .push(title.as_ref())
+            .push("\"");
+        if surface != SurfaceKind::Content {
+            heading.push(format!(" in its {surface}"));
+        }
+
         CreateEmbed::new()
             .color(Colour::ORANGE)
             .author({
@@ -48,18 +67,8 @@ pub async fn automod_enforced(
             })
             .description(ellipsis_string(
                 MessageBuilder::new()
-                    .push_bold_line(
-                        MessageBuilder::new()
-                            .push("Message sent by ")
-                            .mention(&message.author)
-                            .push(" on ")
-                            .mention(&message.channel_id)
-                            .push(" matched AutoMod rule \"")
-                            .push(title.as_ref())
-                            .push("\"")
-                            .build(),
-                    )
-                    .push(&message.content)
+                    .push_bold_line(heading.build())
+                    .push(text.as_ref())
                     .build(),
                 EMBED_DESC_LENGTH,
             ))
@@ -70,6 +79,35 @@ pub async fn automod_enforced(
     Ok(())
 }
 
+pub async fn automod_escalated(
+    ctx: &Context,
+    guild_id: GuildId,
+    user: &User,
+    action: impl AsRef<str>,
+    reason: impl AsRef<str>,
+) -> Result<()> {
+    send_log(ctx, guild_id, || {
+        CreateEmbed::new()
+            .color(Colour::DARK_ORANGE)
+            .author(CreateEmbedAuthor::new(user.tag()).icon_url(user.face()))
+            .description(
+                MessageBuilder::new()
+                    .push_bold_line(
+                        MessageBuilder::new()
+                            .mention(user)
+                            .push(" was ")
+                            .push(action.as_ref())
+                            .push(" by AutoMod escalation")
+                            .build(),
+                    )
+                    .push_safe(reason.as_ref())
+                    .build(),
+            )
+    })
+    .await?;
+    Ok(())
+}
+
 pub async fn message_deleted(
     ctx: &Context,
     channel_id: ChannelId,
@@ -123,16 +161,46 @@ pub async fn message_deleted(
     Ok(())
 }
 
-pub async fn member_added(ctx: &Context, guild_id: GuildId, user: &User) -> Result<()> {
+pub async fn member_added(
+    ctx: &Context,
+    guild_id: GuildId,
+    user: &User,
+    restored: &Restored,
+) -> Result<()> {
     send_log(ctx, guild_id, || {
+        let mut description = MessageBuilder::new();
+        if restored.is_empty() {
+            description.push_bold(MessageBuilder::new().mention(user).push(" joined").build());
+        } else {
+            description.push_bold_line(
+                MessageBuilder::new()
+                    .mention(user)
+                    .push(" rejoined")
+                    .build(),
+            );
+            if !restored.role_ids.is_empty() {
+                description.push("Restored roles: ").push_line(
+                    restored
+                        .role_ids
+                        .iter()
+                        .map(|role_id| role_id.mention().to_string())
+                        .join(", "),
+                );
+            }
+            if let Some(ref nick) = restored.nick {
+                description.push("Restored nickname: ").push_line_safe(nick);
+            }
+            if let Some(until) = restored.timeout_until {
+                description.push_line(format!(
+                    "Re-applied timeout until <t:{0}:f> (<t:{0}:R>)",
+                    until.timestamp()
+                ));
+            }
+        }
         CreateEmbed::new()
             .color(Colour::DARK_GREEN)
             .author(CreateEmbedAuthor::new(user.tag()).icon_url(user.face()))
-            .description(
-                MessageBuilder::new()
-                    .push_bold(MessageBuilder::new().mention(user).push(" joined").build())
-                    .build(),
-            )
+            .description(ellipsis_string(description.build(), EMBED_DESC_LENGTH))
     })
     .await?;
     Ok(())
@@ -4,20 +4,26 @@ use crate::config::Config;
 use color_eyre::eyre::{Result, eyre};
 use poise::{EditTracker, Framework, FrameworkOptions, PrefixFrameworkOptions};
 use serenity::{
-    cache::Settings as CacheSettings, client::Client, model::gateway::GatewayIntents,
+    all::{FullEvent, GuildId},
+    cache::Settings as CacheSettings,
+    client::Client,
+    model::gateway::GatewayIntents,
     prelude::TypeMapKey,
 };
 
 pub mod automod;
+mod autoroles;
 pub mod commands;
 mod event_handler;
+mod guild_settings;
 pub mod limits;
 mod log_channel;
+mod quotas;
 #[cfg(feature = "starboard")]
 mod starboard;
 mod stats;
 mod sticky_roles;
-mod volatiles;
+pub mod volatiles;
 
 #[derive(Debug)]
 pub struct ActivityKey;
@@ -61,6 +67,51 @@ where
     data.entry::<T>().or_insert_with(f).clone()
 }
 
+/// Marks `T` as present, returning whether it wasn't already, for doing things once per process.
+pub async fn insert_once<T>(ctx: &serenity::all::Context) -> bool
+where
+    T: TypeMapKey<Value = ()>,
+{
+    let mut data = ctx.data.write().await;
+    if data.contains_key::<T>() {
+        return false;
+    }
+    data.insert::<T>(());
+    true
+}
+
+/// Runs `f` in the background every `interval`, or back to back if it waits by itself.
+/// Spawns once per `T`, no matter how often the client reconnects.
+pub async fn spawn_once<T, F, Fut>(ctx: &serenity::all::Context, interval: Duration, f: F)
+where
+    T: TypeMapKey<Value = ()>,
+    F: Fn(serenity::all::Context) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send,
+{
+    if !insert_once::<T>(ctx).await {
+        return;
+    }
+
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(interval).await;
+            f(ctx.clone()).await;
+        }
+    });
+}
+
+/// Registers the slash commands in the guild, which is instant unlike registering globally.
+async fn register_commands(
+    ctx: &serenity::all::Context,
+    commands: &[poise::Command<PoiseData, crate::Error>],
+    guild_id: GuildId,
+) {
+    if let Err(err) = poise::builtins::register_in_guild(ctx, commands, guild_id).await {
+        log::warn!("Unable to register slash commands in {guild_id}: {err:?}");
+    }
+}
+
 #[derive(Debug)]
 struct PoiseData {
     config: Config,
@@ -80,8 +131,11 @@ impl Discord {
     ) -> Result<Self> {
         let setup_config = config.clone();
         let framework = Framework::<PoiseData, crate::Error>::builder()
-            .setup(|_ctx, _ready, _framework| {
+            .setup(|ctx, ready, framework| {
                 Box::pin(async move {
+                    for guild in &ready.guilds {
+                        register_commands(ctx, &framework.options().commands, guild.id).await;
+                    }
                     Ok(PoiseData {
                         config: setup_config,
                     })
@@ -89,7 +143,18 @@ impl Discord {
             })
             .options(FrameworkOptions {
                 prefix_options: PrefixFrameworkOptions {
-                    prefix: Some(config.discord.command_prefix.to_string()),
+                    dynamic_prefix: Some(|ctx| {
+                        Box::pin(async move {
+                            Ok(Some(
+                                guild_settings::prefix(
+                                    ctx.serenity_context,
+                                    &ctx.data.config,
+                                    ctx.guild_id,
+                                )
+                                .await,
+                            ))
+                        })
+                    }),
                     mention_as_prefix: false,
                     edit_tracker: Some(Arc::new(EditTracker::for_timespan(Duration::from_secs(
                         3600,
@@ -97,22 +162,20 @@ impl Discord {
                     ..Default::default()
                 },
                 owners: config.discord.owners.clone(),
-                command_check: Some(|ctx| {
+                command_check: Some(|ctx| Box::pin(commands::command_check(ctx))),
+                event_handler: |ctx, event, framework, _data| {
                     Box::pin(async move {
-                        Ok(ctx
-                            .data()
-                            .config
-                            .discord
-                            .command_channels
-                            .contains(&ctx.channel_id())
-                            && !ctx
-                                .data()
-                                .config
-                                .discord
-                                .blocked_users
-                                .contains(&ctx.author().id))
+                        // guilds joined after startup
+                        if let FullEvent::GuildCreate {
+                            guild,
+                            is_new: Some(true),
+                        } = event
+                        {
+                            register_commands(ctx, &framework.options.commands, guild.id).await;
+                        }
+                        Ok(())
                     })
-                }),
+                },
                 commands: commands::get_all(),
                 on_error: |err| {
                     Box::pin(async move {
@@ -142,7 +205,7 @@ impl Discord {
             builder.type_map_insert::<crate::openai::OpenAiKey>(std::sync::Arc::new(openai));
 
         #[cfg(feature = "starboard")]
-        let builder = builder.type_map_insert::<starboard::StarboardKey>(starboard::spawn());
+        let builder = builder.type_map_insert::<starboard::StarboardKey>(Arc::default());
 
         Ok(Self {
             client: builder.await?,
@@ -0,0 +1,165 @@
+use super::{ConfigKey, DbKey, get_data};
+use crate::{
+    config::QuotaConfig,
+    util::{format_duration_long, try_seconds},
+};
+use bson::{doc, serde_helpers::datetime::FromChrono04DateTime};
+use chrono::{DateTime, TimeDelta, Utc};
+use color_eyre::eyre::Result;
+use futures::TryStreamExt;
+use mongodb::Collection;
+use serde::{Deserialize, Serialize};
+use serenity::all::{Context, CreateMessage, Mentionable, Message};
+
+pub const COLLECTION_NAME: &str = "quota-posts";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct QuotaPost {
+    channel_id: String,
+    user_id: String,
+    message_id: String,
+    #[serde(with = "FromChrono04DateTime")]
+    time: DateTime<Utc>,
+    /// When the post stops counting towards the quota, for the TTL index.
+    #[serde(with = "FromChrono04DateTime")]
+    expires_at: DateTime<Utc>,
+}
+
+/// The quota period, practically forever if it's too long to represent.
+fn period(config: &QuotaConfig) -> TimeDelta {
+    try_seconds(config.per).unwrap_or(TimeDelta::MAX)
+}
+
+/// How long until the user can post again, given the times of their posts in the current period.
+fn wait_time(
+    config: &QuotaConfig,
+    times: &[DateTime<Utc>],
+    now: DateTime<Utc>,
+) -> Option<TimeDelta> {
+    let per = period(config);
+    let mut times: Vec<_> = times
+        .iter()
+        .filter(|time| time.checked_add_signed(per).is_none_or(|end| end > now))
+        .collect();
+    if times.len() < config.max {
+        return None;
+    }
+    times.sort_unstable();
+    // the post that has to drop out of the period before there's room for another one
+    Some(
+        times
+            .get(times.len() - config.max)
+            .and_then(|time| time.checked_add_signed(per))
+            .map_or(per, |end| end - now),
+    )
+}
+
+/// Deletes the message if the user is over the quota, returning whether it was.
+pub async fn enforce(ctx: &Context, msg: &Message) -> Result<bool> {
+    let config = get_data::<ConfigKey>(ctx).await?;
+    let Some(config) = config
+        .discord
+        .quotas
+        .iter()
+        .find(|quota| quota.channel == msg.channel_id)
+    else {
+        // not enabled for this channel
+        return Ok(false);
+    };
+    if msg.member.as_ref().is_some_and(|member| {
+        member
+            .roles
+            .iter()
+            .any(|role| config.exempt_roles.contains(role))
+    }) {
+        return Ok(false);
+    }
+
+    let collection: Collection<QuotaPost> =
+        get_data::<DbKey>(ctx).await?.collection(COLLECTION_NAME);
+    let now = Utc::now();
+    let per = period(config);
+    let times: Vec<DateTime<Utc>> = collection
+        .find(doc! {
+            "channel_id": msg.channel_id.to_string(),
+            "user_id": msg.author.id.to_string(),
+            "time": { "$gt": now.checked_sub_signed(per).unwrap_or(DateTime::<Utc>::MIN_UTC) },
+        })
+        .await?
+        .map_ok(|post| post.time)
+        .try_collect()
+        .await?;
+
+    if let Some(wait) = wait_time(config, &times, now) {
+        log::info!("Over quota message from {}", msg.author.tag());
+        msg.delete(ctx).await?;
+        let notice = format!(
+            "You can only post {} times per {} in {}. You can post again in {}.",
+            config.max,
+            format_duration_long(&per.to_std()?),
+            msg.channel_id.mention(),
+            format_duration_long(&wait.to_std()?)
+        );
+        if let Err(err) = msg
+            .author
+            .direct_message(ctx, CreateMessage::new().content(notice))
+            .await
+        {
+            log::warn!("Unable to DM quota notice: {err:?}");
+        }
+        return Ok(true);
+    }
+
+    collection
+        .insert_one(QuotaPost {
+            channel_id: msg.channel_id.to_string(),
+            user_id: msg.author.id.to_string(),
+            message_id: msg.id.to_string(),
+            time: now,
+            expires_at: now
+                .checked_add_signed(per)
+                .unwrap_or(DateTime::<Utc>::MAX_UTC),
+        })
+        .await?;
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::wait_time;
+    use crate::config::QuotaConfig;
+    use chrono::{TimeDelta, Utc};
+    use maplit::hashset;
+    use serenity::all::ChannelId;
+
+    #[test]
+    fn sliding_window() {
+        let now = Utc::now();
+        let config = QuotaConfig {
+            channel: ChannelId::new(1),
+            max: 2,
+            per: 60 * 60,
+            exempt_roles: hashset! {},
+        };
+        let ago = |minutes| now - TimeDelta::minutes(minutes);
+
+        assert_eq!(wait_time(&config, &[], now), None);
+        assert_eq!(wait_time(&config, &[ago(10)], now), None);
+        assert_eq!(wait_time(&config, &[ago(90), ago(10)], now), None);
+        assert_eq!(
+            wait_time(&config, &[ago(10), ago(40)], now),
+            Some(TimeDelta::minutes(20))
+        );
+        assert_eq!(
+            wait_time(&config, &[ago(5), ago(10), ago(40)], now),
+            Some(TimeDelta::minutes(50))
+        );
+
+        let forever = QuotaConfig {
+            per: u64::MAX,
+            ..config
+        };
+        assert_eq!(wait_time(&forever, &[ago(90)], now), None);
+        assert!(wait_time(&forever, &[ago(90), ago(10)], now).is_some());
+    }
+}
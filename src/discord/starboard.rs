@@ -1,78 +1,375 @@
-use std::time::Duration;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
 
-use bson::doc;
+use bson::{doc, serde_helpers::datetime::FromChrono04DateTime};
+use chrono::{DateTime, TimeDelta, Utc};
 use color_eyre::eyre::{OptionExt, Result};
+use futures::TryStreamExt;
 use log::warn;
+use mongodb::Collection;
 use rand::{RngExt, SeedableRng, distr::Uniform};
 use rand_pcg::Pcg32;
 use serde::{Deserialize, Serialize};
 use serenity::{
     all::{
-        Color, Context, CreateAllowedMentions, CreateAttachment, CreateEmbed, CreateEmbedAuthor,
-        CreateMessage, Guild, Message, MessageBuilder, PermissionOverwriteType, Permissions,
-        Reaction, ReactionType, Role, RoleId,
+        ChannelId, ChannelType, Color, Context, CreateAllowedMentions, CreateAttachment,
+        CreateEmbed, CreateEmbedAuthor, CreateMessage, EditMessage, GetMessages, Guild,
+        GuildChannel, GuildId, Message, MessageBuilder, MessageId, MessageReference,
+        MessageReferenceKind, PermissionOverwriteType, Permissions, Reaction, ReactionType, Role,
+        RoleId, StatusCode, User, UserId,
     },
     prelude::TypeMapKey,
 };
-use tokio::sync::mpsc::{UnboundedSender, unbounded_channel};
+use tokio::sync::Notify;
 
 use crate::{
-    config::Config,
-    discord::{DbKey, get_data},
+    config::{BoardConfig, Config, NsfwPolicy, RenderMode, StarboardConfig},
+    discord::{ConfigKey, DbKey, get_data, insert_once, spawn_once, stats::member_message_count},
+    util::try_seconds,
 };
 
 const COLLECTION_NAME: &str = "starboard";
 const MAX_ATTACH_SIZE: u32 = 10 * 1000 * 1000; // 10 MiB
+const REACTION_USERS_PAGE: u8 = 100;
 
-pub type Sender = UnboundedSender<(Context, Reaction)>;
+const QUEUE_COLLECTION_NAME: &str = "starboard-queue";
+/// How long reactions settle before a message is processed.
+const QUEUE_DELAY: TimeDelta = TimeDelta::seconds(3);
+const QUEUE_IDLE: Duration = Duration::from_secs(60);
+/// Times a message is processed before it's given up on.
+const QUEUE_MAX_ATTEMPTS: i64 = 5;
+/// How much longer a message waits after every failed attempt.
+const QUEUE_BACKOFF: TimeDelta = TimeDelta::minutes(1);
 
+/// Wakes the queue worker when something was queued.
 #[derive(Debug)]
 pub struct StarboardKey;
 
 impl TypeMapKey for StarboardKey {
-    type Value = Sender;
+    type Value = Arc<Notify>;
+}
+
+#[derive(Debug)]
+struct WorkerKey;
+
+impl TypeMapKey for WorkerKey {
+    type Value = ();
+}
+
+#[derive(Debug)]
+struct CatchUpKey;
+
+impl TypeMapKey for CatchUpKey {
+    type Value = ();
+}
+
+/// A message whose reactions changed, waiting to be processed.
+/// There's at most one per message, however often it's queued.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct QueuedMessage {
+    message_id: String,
+    channel_id: String,
+    guild_id: String,
+    /// When it was first queued, pushed back after failed attempts.
+    #[serde(with = "FromChrono04DateTime")]
+    time: DateTime<Utc>,
+    /// Bumped whenever it's queued again, so changes during processing aren't lost.
+    version: i64,
+    /// Failed attempts at processing it.
+    #[serde(default)]
+    attempts: i64,
+}
+
+async fn queue_collection(ctx: &Context) -> Result<Collection<QueuedMessage>> {
+    Ok(get_data::<DbKey>(ctx)
+        .await?
+        .collection(QUEUE_COLLECTION_NAME))
+}
+
+/// Persists that the message needs processing and wakes the worker.
+async fn queue(
+    ctx: &Context,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    message_id: MessageId,
+) -> Result<()> {
+    queue_collection(ctx)
+        .await?
+        .update_one(
+            doc! { "message_id": message_id.to_string() },
+            doc! {
+                "$setOnInsert": {
+                    "channel_id": channel_id.to_string(),
+                    "guild_id": guild_id.to_string(),
+                    "time": Utc::now(),
+                },
+                "$inc": { "version": 1_i64 },
+            },
+        )
+        .upsert(true)
+        .await?;
+    get_data::<StarboardKey>(ctx).await?.notify_one();
+    Ok(())
 }
 
-pub fn spawn() -> Sender {
-    let (sender, mut receiver) = unbounded_channel();
-    tokio::spawn(async move {
-        while let Some((ctx, reaction)) = receiver.recv().await {
-            if let Err(err) = on_reaction_change(ctx, reaction).await {
-                log::error!("Error in starboard reaction handler: {err:?}");
+/// Queues the reacted message if the reaction is a star of one of the guild's boards.
+pub async fn enqueue(ctx: Context, reaction: Reaction) -> Result<()> {
+    let guild_id = reaction.guild_id.ok_or_eyre("no guild id")?;
+    let config = get_data::<ConfigKey>(&ctx).await?;
+    let is_star = {
+        let guild = guild_id
+            .to_guild_cached(&ctx)
+            .ok_or_eyre("Guild not in cache")?;
+        guild_boards(&config, &guild).any(|board| is_star_emoji(board, &reaction.emoji))
+    };
+    if !is_star {
+        return Ok(());
+    }
+    queue(&ctx, guild_id, reaction.channel_id, reaction.message_id).await
+}
+
+fn is_not_found(err: &color_eyre::Report) -> bool {
+    matches!(
+        err.downcast_ref::<serenity::Error>(),
+        Some(serenity::Error::Http(err)) if err.status_code() == Some(StatusCode::NOT_FOUND)
+    )
+}
+
+/// Processes queued messages until the queue is empty or the rest isn't due yet,
+/// returning how long until the next one is.
+async fn drain_queue(ctx: &Context) -> Result<Option<Duration>> {
+    let collection = queue_collection(ctx).await?;
+    loop {
+        let Some(entry) = collection
+            .find_one(doc! {})
+            .sort(doc! { "time": 1 })
+            .await?
+        else {
+            return Ok(None);
+        };
+        let due = entry.time + QUEUE_DELAY;
+        let now = Utc::now();
+        if due > now {
+            return Ok(Some((due - now).to_std()?));
+        }
+
+        // stays queued if it was queued again in the meantime
+        let filter = doc! {
+            "message_id": &entry.message_id,
+            "version": entry.version,
+        };
+        match process(ctx, &entry).await {
+            Ok(()) => {
+                collection.delete_one(filter).await?;
+            }
+            // deleted in the meantime, retrying won't bring it back
+            Err(err) if is_not_found(&err) => {
+                log::trace!("Starboard message of {entry:?} is gone: {err:?}");
+                collection.delete_one(filter).await?;
+            }
+            Err(err) if entry.attempts + 1 >= QUEUE_MAX_ATTEMPTS => {
+                log::error!("Giving up on starboard for {entry:?}: {err:?}");
+                collection.delete_one(filter).await?;
+            }
+            Err(err) => {
+                log::warn!("Error in starboard for {entry:?}, retrying: {err:?}");
+                let attempts = entry.attempts + 1;
+                collection
+                    .update_one(
+                        filter,
+                        doc! { "$set": {
+                            "time": now + QUEUE_BACKOFF * i32::try_from(attempts)?,
+                            "attempts": attempts,
+                        } },
+                    )
+                    .await?;
             }
         }
-    });
-    sender
+    }
 }
 
-pub async fn enqueue(ctx: Context, reaction: Reaction) -> Result<()> {
-    let mut data = ctx.data.write().await;
-    if data
-        .get::<StarboardKey>()
-        .is_none_or(|sender| sender.send((ctx.clone(), reaction.clone())).is_err())
-    {
-        log::warn!("Respawning starboard task");
-        let sender = spawn();
-        sender.send((ctx.clone(), reaction))?;
-        data.insert::<StarboardKey>(sender);
+/// Works through the queue, including what was left over from before a restart.
+pub async fn spawn_worker(ctx: &Context) -> Result<()> {
+    let notify = get_data::<StarboardKey>(ctx).await?;
+    // waits by itself until something is due or gets queued
+    spawn_once::<WorkerKey, _, _>(ctx, Duration::ZERO, move |ctx| {
+        let notify = Arc::clone(&notify);
+        async move {
+            let wait = match drain_queue(&ctx).await {
+                Ok(wait) => wait.unwrap_or(QUEUE_IDLE),
+                Err(err) => {
+                    log::error!("Unable to drain starboard queue: {err:?}");
+                    QUEUE_IDLE
+                }
+            };
+            tokio::select! {
+                () = notify.notified() => {}
+                () = tokio::time::sleep(wait) => {}
+            }
+        }
+    })
+    .await;
+    Ok(())
+}
+
+/// Queues recent messages with stars in every channel and active thread that could reach a
+/// board, to catch reactions made while we were offline.
+/// Only runs once, no matter how often the client reconnects.
+pub async fn catch_up(ctx: &Context, guild_ids: &[GuildId]) -> Result<()> {
+    let config = get_data::<ConfigKey>(ctx).await?;
+    if config.starboard.catch_up == 0 || !insert_once::<CatchUpKey>(ctx).await {
+        return Ok(());
+    }
+
+    for guild_id in guild_ids {
+        let Some(guild) = guild_id.to_guild_cached(ctx).map(|guild| guild.clone()) else {
+            continue;
+        };
+        let boards: Vec<&BoardConfig> = guild_boards(&config, &guild).collect();
+        if boards.is_empty() {
+            continue;
+        }
+
+        let channels = guild
+            .channels
+            .values()
+            .filter(|channel| matches!(channel.kind, ChannelType::Text | ChannelType::News))
+            .chain(&guild.threads)
+            .filter(|channel| {
+                // threads go by their parent
+                let channel = channel
+                    .thread_metadata
+                    .and(channel.parent_id)
+                    .and_then(|parent_id| guild.channels.get(&parent_id))
+                    .unwrap_or(channel);
+                !config
+                    .starboard
+                    .boards
+                    .iter()
+                    .any(|board| board.channel == channel.id)
+                    && !is_ignored_channel(&config, &guild, channel)
+            });
+        let mut queued = 0;
+        for channel in channels {
+            let messages = match channel
+                .id
+                .messages(ctx, GetMessages::new().limit(config.starboard.catch_up))
+                .await
+            {
+                Ok(messages) => messages,
+                Err(err) => {
+                    log::trace!("Unable to catch up on {}: {err:?}", channel.name);
+                    continue;
+                }
+            };
+            for message in messages.iter().filter(|message| {
+                message.reactions.iter().any(|reaction| {
+                    boards
+                        .iter()
+                        .any(|board| is_star_emoji(board, &reaction.reaction_type))
+                })
+            }) {
+                if let Err(err) = queue(ctx, guild.id, channel.id, message.id).await {
+                    log::error!("Unable to queue {} to catch up: {err:?}", message.id);
+                    continue;
+                }
+                queued += 1;
+            }
+        }
+        log::info!(
+            "Queued {queued} starred messages in {} to catch up",
+            guild.name
+        );
     }
     Ok(())
 }
 
+/// A message on the board. Everything but the ID is unknown for old entries.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-struct StarredMessage {
-    message_id: String,
+pub struct StarredMessage {
+    pub message_id: String,
+    /// Name of the board, see [`BoardConfig`].
+    #[serde(default)]
+    pub board: String,
+    #[serde(default)]
+    pub channel_id: Option<String>,
+    #[serde(default)]
+    pub guild_id: Option<String>,
+    #[serde(default)]
+    pub author_id: Option<String>,
+    #[serde(default)]
+    pub board_channel_id: Option<String>,
+    #[serde(default)]
+    pub board_message_id: Option<String>,
+    /// How the message was posted to the board.
+    #[serde(default)]
+    pub mode: RenderMode,
+    /// The forward the board message replies to, in hybrid mode.
+    #[serde(default)]
+    pub forward_message_id: Option<String>,
+    /// Stars as of the last update.
+    #[serde(default)]
+    pub count: u64,
+    /// When the message made it onto the board.
+    #[serde(default)]
+    pub time: Option<bson::DateTime>,
+}
+
+/// Stars received by a member over all their messages on the board.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AuthorStars {
+    #[serde(rename = "_id")]
+    pub author_id: String,
+    pub stars: u64,
+    pub messages: u64,
+}
+
+impl StarredMessage {
+    fn board_message(&self) -> Option<(ChannelId, MessageId)> {
+        let channel_id = self.board_channel_id.as_ref()?.parse().ok()?;
+        let message_id = self.board_message_id.as_ref()?.parse().ok()?;
+        Some((ChannelId::new(channel_id), MessageId::new(message_id)))
+    }
+
+    /// Deletes everything that was posted to the board for the message.
+    async fn delete_from_board(&self, ctx: &Context) -> Result<()> {
+        let Some((board_channel_id, board_message_id)) = self.board_message() else {
+            return Ok(());
+        };
+        board_channel_id
+            .delete_message(ctx, board_message_id)
+            .await?;
+        if let Some(forward_message_id) = &self.forward_message_id {
+            board_channel_id
+                .delete_message(ctx, MessageId::new(forward_message_id.parse()?))
+                .await?;
+        }
+        Ok(())
+    }
 }
 
-fn is_star_emoji(config: &Config, emoji: &ReactionType) -> bool {
+/// The classic "⭐ 12" header of board messages.
+/// Messages fetched over HTTP don't know their guild, so it's passed in for the link.
+pub fn header(emoji: &str, count: u64, message: &Message, guild_id: GuildId) -> String {
+    MessageBuilder::new()
+        .push(emoji)
+        .push(" ")
+        .push_bold(count.to_string())
+        .push(" ")
+        .push(message.id.link(message.channel_id, Some(guild_id)))
+        .build()
+}
+
+fn is_star_emoji(board: &BoardConfig, emoji: &ReactionType) -> bool {
     match emoji {
         ReactionType::Custom { id, name, .. } => {
-            id.to_string() == *config.starboard.emoji
-                || name
-                    .as_ref()
-                    .is_some_and(|name| name == &*config.starboard.emoji)
+            id.to_string() == *board.emoji
+                || name.as_ref().is_some_and(|name| name == &*board.emoji)
         }
-        ReactionType::Unicode(s) => s == &*config.starboard.emoji,
+        ReactionType::Unicode(s) => s == &*board.emoji,
         emoji => {
             warn!("Unknown reaction type: {emoji:?}");
             false
@@ -80,54 +377,113 @@ fn is_star_emoji(config: &Config, emoji: &ReactionType) -> bool {
     }
 }
 
-#[allow(clippy::too_many_lines)]
-async fn on_reaction_change(ctx: Context, reaction: Reaction) -> Result<()> {
-    // log::trace!("{reaction:?}");
+/// The boards that post to a channel of the guild.
+pub fn guild_boards<'a>(
+    config: &'a Config,
+    guild: &'a Guild,
+) -> impl Iterator<Item = &'a BoardConfig> {
+    config
+        .starboard
+        .boards
+        .iter()
+        .filter(|board| guild.channels.contains_key(&board.channel))
+}
 
-    let guild_id = reaction.guild_id.ok_or_eyre("no guild id")?;
-    let guild = guild_id
-        .to_guild_cached(&ctx)
+/// The guild's board with the given name, or its first one.
+pub fn find_board<'a>(
+    config: &'a Config,
+    guild: &'a Guild,
+    name: Option<&str>,
+) -> Option<&'a BoardConfig> {
+    guild_boards(config, guild).find(|board| name.is_none_or(|name| board.name == name))
+}
+
+async fn process(ctx: &Context, entry: &QueuedMessage) -> Result<()> {
+    let guild = GuildId::new(entry.guild_id.parse()?)
+        .to_guild_cached(ctx)
         .ok_or_eyre("Guild not in cache")?
         .clone();
-
-    let config = super::get_data::<super::ConfigKey>(&ctx).await?;
-    if !is_star_emoji(&config, &reaction.emoji) {
-        // log::trace!("not a star emoji");
+    let config = get_data::<ConfigKey>(ctx).await?;
+    let boards: Vec<&BoardConfig> = guild_boards(&config, &guild).collect();
+    if boards.is_empty() {
         return Ok(());
     }
 
-    let collection = get_data::<DbKey>(&ctx)
+    let message = ctx
+        .http
+        .get_message(
+            ChannelId::new(entry.channel_id.parse()?),
+            MessageId::new(entry.message_id.parse()?),
+        )
+        .await?;
+
+    for board in boards {
+        on_board_reaction(ctx, &guild, &config, board, &message).await?;
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_lines)]
+async fn on_board_reaction(
+    ctx: &Context,
+    guild: &Guild,
+    config: &Config,
+    board: &BoardConfig,
+    message: &Message,
+) -> Result<()> {
+    let collection = get_data::<DbKey>(ctx)
         .await?
         .collection::<StarredMessage>(COLLECTION_NAME);
-    if collection
+    let starred = collection
         .find_one(doc! {
-            "message_id": reaction.message_id.to_string()
+            "message_id": message.id.to_string(),
+            "board": &board.name,
         })
-        .await?
-        .is_some()
+        .await?;
+    if starred
+        .as_ref()
+        .is_some_and(|starred| starred.board_message().is_none())
     {
-        log::trace!("{} already on board", reaction.message_id);
+        log::trace!("{} already on board {}", message.id, board.name);
         return Ok(());
     }
 
-    tokio::time::sleep(Duration::from_secs(3)).await;
+    let threshold = if let Some(max_threshold) = board.max_threshold {
+        let distr = Uniform::new_inclusive(board.threshold, max_threshold)?;
+        let mut rand = Pcg32::seed_from_u64(message.id.get());
+        rand.sample(distr)
+    } else {
+        board.threshold
+    };
 
-    // let message = if let Some(msg) = ctx.cache.message(reaction.channel_id, reaction.message_id) {
-    //     msg.to_owned()
-    // } else {
-    //     ctx.http
-    //         .get_message(reaction.channel_id, reaction.message_id)
-    //         .await?
-    // };
+    // the count on the board has to be exact, even below the threshold
+    let skip_below = starred.is_none().then_some(threshold);
+    let stars = count_stars(ctx, guild, config, board, message, skip_below).await?;
+    if stars.is_none() && starred.is_none() {
+        log::trace!("not starred on {}", board.name);
+        return Ok(());
+    }
 
-    let message = ctx
-        .http
-        .get_message(reaction.channel_id, reaction.message_id)
-        .await?;
+    if let Some(ref starred) = starred {
+        let (emoji, count) = stars.map_or_else(
+            || (board.emoji.to_string(), 0),
+            |(emoji, count)| (emoji.to_string(), count),
+        );
+        return update_board(
+            ctx,
+            config,
+            board,
+            starred,
+            &header(&emoji, count, message, guild.id),
+            count,
+            count >= threshold,
+        )
+        .await;
+    }
 
     let mut channels = vec![
         message
-            .channel(&ctx)
+            .channel(ctx)
             .await?
             .guild()
             .ok_or_eyre("Message not in guild channel")?,
@@ -135,63 +491,50 @@ async fn on_reaction_change(ctx: Context, reaction: Reaction) -> Result<()> {
     while let Some(parent_id) = channels.last().unwrap().parent_id {
         channels.push(
             parent_id
-                .to_channel(&ctx)
+                .to_channel(ctx)
                 .await?
                 .guild()
                 .ok_or_eyre("Parent channel not a guild channel")?,
         );
     }
+    let channel_ids: HashSet<ChannelId> = channels.iter().map(|c| c.id).collect();
 
-    if !config
+    if config
         .starboard
-        .channels
-        .is_disjoint(&channels.iter().map(|c| c.id).collect())
+        .boards
+        .iter()
+        .any(|board| channel_ids.contains(&board.channel))
     {
         log::trace!("starboard channel, ignoring");
         return Ok(());
     }
 
-    for role in guild_roles(&guild, &config.starboard.ignore_channels) {
-        for channel in &channels {
-            if channel.permission_overwrites.iter().any(|overwrite| {
-                overwrite.kind == PermissionOverwriteType::Role(role.id)
-                    && overwrite.deny.contains(Permissions::VIEW_CHANNEL)
-            }) {
-                log::trace!("channel ignored by role");
-                return Ok(());
-            }
-        }
+    if !board.sources.is_empty() && board.sources.is_disjoint(&channel_ids) {
+        log::trace!("not a source channel of {}", board.name);
+        return Ok(());
     }
 
-    let threshold = if let Some(max_threshold) = config.starboard.max_threshold {
-        let distr = Uniform::new_inclusive(config.starboard.threshold, max_threshold)?;
-        let mut rand = Pcg32::seed_from_u64(reaction.message_id.get());
-        rand.sample(distr)
-    } else {
-        config.starboard.threshold
-    };
-
-    let Some(message_reaction) = message
-        .reactions
+    if channels
         .iter()
-        .find(|r| is_star_emoji(&config, &r.reaction_type))
-    else {
+        .any(|channel| is_ignored_channel(config, guild, channel))
+    {
+        log::trace!("channel ignored by role");
+        return Ok(());
+    }
+
+    let Some((emoji, reaction_count)) = stars else {
         log::trace!("no star reaction found");
         return Ok(());
     };
-    if message_reaction.count < threshold {
-        // log::trace!(
-        //     "total {} {} < {threshold}",
-        //     message_reaction.count,
-        //     message_reaction.reaction_type
-        // );
+    if reaction_count < threshold {
+        log::trace!("filtered {reaction_count} {emoji} < {threshold}");
         return Ok(());
     }
 
-    for role in guild_roles(&guild, &config.starboard.ignore_messages) {
+    for role in guild_roles(guild, &config.starboard.ignore_messages) {
         if message
             .author
-            .has_role(&ctx, guild_id, role.id)
+            .has_role(ctx, guild.id, role.id)
             .await
             .unwrap_or(false)
         {
@@ -200,122 +543,431 @@ async fn on_reaction_change(ctx: Context, reaction: Reaction) -> Result<()> {
         }
     }
 
-    let reaction_users = message
-        .reaction_users(
-            &ctx,
-            message_reaction.reaction_type.clone(),
-            Some(100),
-            None,
-        )
-        .await?;
-    let mut reaction_count = 0;
-    for role in guild_roles(&guild, &config.starboard.ignore_stars) {
-        for user in &reaction_users {
-            if !user
-                .has_role(&ctx, guild_id, role.id)
-                .await
-                .unwrap_or(false)
-            {
-                reaction_count += 1;
-            }
-        }
-    }
-    if reaction_count < threshold {
-        log::trace!(
-            "filtered {reaction_count} {} < {threshold}",
-            message_reaction.reaction_type
-        );
-        return Ok(());
-    }
-
-    let Some(board_channel_id) = config
-        .starboard
-        .channels
-        .iter()
-        .find(|id| guild.channels.contains_key(id))
-    else {
-        log::trace!("no starboard channel found");
-        return Ok(());
-    };
-    let board_channel = board_channel_id
-        .to_channel(&ctx)
+    let board_channel = board
+        .channel
+        .to_channel(ctx)
         .await?
         .guild()
         .ok_or_eyre("Starboard not a guild channel")?;
 
-    if channels.iter().any(|c| c.nsfw) && !board_channel.nsfw {
-        log::trace!("message is NSFW, but starboard is not");
+    let nsfw = channels.iter().any(|c| c.nsfw);
+    let allowed = match board.nsfw {
+        NsfwPolicy::Inherit => !nsfw || board_channel.nsfw,
+        NsfwPolicy::Exclude => !nsfw,
+        NsfwPolicy::Only => nsfw,
+    };
+    if !allowed {
+        log::trace!("NSFW policy of {} doesn't allow message", board.name);
         return Ok(());
     }
 
-    // board_channel
-    //     .send_message(
-    //         ctx,
-    //         CreateMessage::new().reference_message(
-    //             MessageReference::new(MessageReferenceKind::Forward, message.channel_id)
-    //                 .message_id(message.id),
-    //         ),
-    //     )
-    //     .await?;
+    log::trace!("Sending starboard");
+    let header = header(&emoji.to_string(), reaction_count, message, guild.id);
+    let (board_message, forward_message) = match board.mode {
+        RenderMode::Upload => (
+            board_channel
+                .send_message(ctx, upload(ctx, guild, message, header).await?)
+                .await?,
+            None,
+        ),
+        RenderMode::Forward => (
+            board_channel.send_message(ctx, forward(message)).await?,
+            None,
+        ),
+        RenderMode::Hybrid => {
+            let forward_message = board_channel.send_message(ctx, forward(message)).await?;
+            let board_message = board_channel
+                .send_message(
+                    ctx,
+                    CreateMessage::new()
+                        .allowed_mentions(CreateAllowedMentions::new())
+                        .content(header)
+                        .reference_message(&forward_message),
+                )
+                .await?;
+            (board_message, Some(forward_message))
+        }
+    };
+
+    collection
+        .insert_one(StarredMessage {
+            message_id: message.id.to_string(),
+            board: board.name.clone(),
+            channel_id: Some(message.channel_id.to_string()),
+            guild_id: Some(guild.id.to_string()),
+            author_id: Some(message.author.id.to_string()),
+            board_channel_id: Some(board_message.channel_id.to_string()),
+            board_message_id: Some(board_message.id.to_string()),
+            mode: board.mode,
+            forward_message_id: forward_message.map(|forward| forward.id.to_string()),
+            count: reaction_count,
+            time: Some(bson::DateTime::now()),
+        })
+        .await?;
+
+    log::info!("Added message to stardboard {}", board.name);
+    Ok(())
+}
 
+/// Re-uploads the message and what it replied to, with their attachments and embeds.
+async fn upload(
+    ctx: &Context,
+    guild: &Guild,
+    message: &Message,
+    header: String,
+) -> Result<CreateMessage> {
     let mut pin = CreateMessage::new()
         .allowed_mentions(CreateAllowedMentions::new())
-        .content(MessageBuilder::new().push(message.link()).build());
+        .content(header);
 
     if let Some(ref replied) = message.referenced_message {
-        for attach in &replied.attachments {
-            if attach.size < MAX_ATTACH_SIZE {
-                pin = pin.add_file(CreateAttachment::bytes(
-                    attach.download().await?,
-                    &attach.filename,
-                ));
-            }
+        for attach in download_attachments(replied).await? {
+            pin = pin.add_file(attach);
         }
-        pin = pin.add_embed(create_embed_from_message(&ctx, &guild, replied).await?);
+        pin = pin.add_embed(create_embed_from_message(ctx, guild, replied).await?);
         for embed in &replied.embeds {
             pin = pin.add_embed(embed.clone().into());
         }
     }
 
-    for attach in &message.attachments {
-        if attach.size < MAX_ATTACH_SIZE {
-            pin = pin.add_file(CreateAttachment::bytes(
-                attach.download().await?,
-                &attach.filename,
-            ));
-        }
+    for attach in download_attachments(message).await? {
+        pin = pin.add_file(attach);
     }
     pin = pin.add_embed(
-        create_embed_from_message(&ctx, &guild, &message)
+        create_embed_from_message(ctx, guild, message)
             .await?
             .color(Color::GOLD),
     );
     for embed in &message.embeds {
         pin = pin.add_embed(embed.clone().into());
     }
+    Ok(pin)
+}
 
-    log::trace!("Sending starboard");
-    board_channel.send_message(&ctx, pin).await?;
+/// Natively forwards the message, which can't have any content of its own.
+fn forward(message: &Message) -> CreateMessage {
+    CreateMessage::new().reference_message(
+        MessageReference::new(MessageReferenceKind::Forward, message.channel_id)
+            .message_id(message.id),
+    )
+}
 
-    collection
-        .insert_one(StarredMessage {
-            message_id: reaction.message_id.to_string(),
+/// Whether a star from `user`, who has `roles`, counts.
+fn is_eligible(config: &StarboardConfig, author_id: UserId, user: &User, roles: &[RoleId]) -> bool {
+    !(config.exclude_author && user.id == author_id
+        || config.exclude_bots && user.bot
+        || roles.iter().any(|role| config.ignore_stars.contains(role)))
+}
+
+/// How much a star from a member with `roles` counts, the highest weight of their roles or 1.
+fn star_weight(config: &StarboardConfig, roles: &[RoleId]) -> u64 {
+    roles
+        .iter()
+        .filter_map(|role| config.role_weights.get(role))
+        .copied()
+        .max()
+        .unwrap_or(1)
+}
+
+/// Whether someone who joined at `joined_at` has been a member long enough for their stars to count.
+fn is_old_enough(
+    config: &StarboardConfig,
+    joined_at: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> bool {
+    config.min_member_age.is_none_or(|min_age| {
+        joined_at.is_some_and(|joined_at| {
+            try_seconds(min_age)
+                .and_then(|min_age| joined_at.checked_add_signed(min_age))
+                .is_some_and(|old_enough_at| old_enough_at <= now)
         })
+    })
+}
+
+/// The star reaction of the message and the weighted total of the stars that count.
+/// When even weighing can't reach `skip_below`, the raw count is returned without weighing or
+/// filtering users.
+async fn count_stars(
+    ctx: &Context,
+    guild: &Guild,
+    config: &Config,
+    board: &BoardConfig,
+    message: &Message,
+    skip_below: Option<u64>,
+) -> Result<Option<(ReactionType, u64)>> {
+    let Some(message_reaction) = message
+        .reactions
+        .iter()
+        .find(|r| is_star_emoji(board, &r.reaction_type))
+    else {
+        return Ok(None);
+    };
+    let max_weight = config
+        .starboard
+        .role_weights
+        .values()
+        .copied()
+        .max()
+        .unwrap_or(1)
+        .max(1);
+    if skip_below
+        .is_some_and(|threshold| message_reaction.count.saturating_mul(max_weight) < threshold)
+    {
+        return Ok(Some((
+            message_reaction.reaction_type.clone(),
+            message_reaction.count,
+        )));
+    }
+
+    let needs_member = !config.starboard.ignore_stars.is_empty()
+        || !config.starboard.role_weights.is_empty()
+        || config.starboard.min_member_age.is_some();
+    let now = Utc::now();
+    let mut stargazers = HashMap::new();
+    let mut after = None;
+    loop {
+        let reaction_users = message
+            .reaction_users(
+                ctx,
+                message_reaction.reaction_type.clone(),
+                Some(REACTION_USERS_PAGE),
+                after,
+            )
+            .await?;
+        for user in &reaction_users {
+            if stargazers.contains_key(&user.id) {
+                continue;
+            }
+            let member = if !needs_member {
+                None
+            } else if let Some(member) = guild.members.get(&user.id) {
+                Some(member.clone())
+            } else {
+                guild.id.member(ctx, user.id).await.ok()
+            };
+            let roles = member.as_ref().map_or(&[][..], |member| &member.roles);
+            let joined_at = member
+                .as_ref()
+                .and_then(|member| member.joined_at)
+                .map(|joined_at| *joined_at);
+
+            let counts = is_eligible(&config.starboard, message.author.id, user, roles)
+                && is_old_enough(&config.starboard, joined_at, now)
+                && match config.starboard.min_messages {
+                    Some(min_messages) => {
+                        member_message_count(ctx, guild.id, user.id).await? >= min_messages
+                    }
+                    None => true,
+                };
+            let weight = if counts {
+                star_weight(&config.starboard, roles)
+            } else {
+                0
+            };
+            stargazers.insert(user.id, weight);
+        }
+        if reaction_users.len() < usize::from(REACTION_USERS_PAGE) {
+            break;
+        }
+        after = reaction_users.last().map(|user| user.id);
+    }
+    Ok(Some((
+        message_reaction.reaction_type.clone(),
+        stargazers.values().sum(),
+    )))
+}
+
+/// Updates the star count of a message already on the board, or removes it if enabled.
+#[allow(clippy::too_many_arguments)]
+async fn update_board(
+    ctx: &Context,
+    config: &Config,
+    board: &BoardConfig,
+    starred: &StarredMessage,
+    header: &str,
+    count: u64,
+    above_threshold: bool,
+) -> Result<()> {
+    let Some((board_channel_id, board_message_id)) = starred.board_message() else {
+        return Ok(());
+    };
+    if !above_threshold && config.starboard.delete_below_threshold {
+        starred.delete_from_board(ctx).await?;
+        get_data::<DbKey>(ctx)
+            .await?
+            .collection::<StarredMessage>(COLLECTION_NAME)
+            .delete_one(doc! {
+                "message_id": &starred.message_id,
+                "board": &board.name,
+            })
+            .await?;
+        log::info!("Removed message from starboard {}", board.name);
+        return Ok(());
+    }
+
+    // forwards can't have content, so there's no header to update
+    if starred.mode != RenderMode::Forward {
+        board_channel_id
+            .edit_message(
+                ctx,
+                board_message_id,
+                EditMessage::new()
+                    .allowed_mentions(CreateAllowedMentions::new())
+                    .content(header),
+            )
+            .await?;
+    }
+    get_data::<DbKey>(ctx)
+        .await?
+        .collection::<StarredMessage>(COLLECTION_NAME)
+        .update_one(
+            doc! {
+                "message_id": &starred.message_id,
+                "board": &board.name,
+            },
+            doc! { "$set": { "count": i64::try_from(count)? } },
+        )
         .await?;
+    log::trace!("Updated starboard");
+    Ok(())
+}
 
-    log::info!("Added message to stardboard");
+/// Removes the board message of a deleted message, if enabled.
+pub async fn on_message_delete(ctx: &Context, message_id: MessageId) -> Result<()> {
+    let config = super::get_data::<super::ConfigKey>(ctx).await?;
+    if !config.starboard.delete_with_original {
+        return Ok(());
+    }
+    let collection = get_data::<DbKey>(ctx)
+        .await?
+        .collection::<StarredMessage>(COLLECTION_NAME);
+    let filter = doc! { "message_id": message_id.to_string() };
+    let starred: Vec<StarredMessage> = collection.find(filter.clone()).await?.try_collect().await?;
+    if starred.is_empty() {
+        return Ok(());
+    }
+    collection.delete_many(filter).await?;
+    for starred in starred
+        .iter()
+        .filter(|starred| starred.board_message().is_some())
+    {
+        starred.delete_from_board(ctx).await?;
+        log::info!("Removed deleted message from starboard");
+    }
     Ok(())
 }
 
-async fn create_embed_from_message(
+/// Members with the most stars, or only the given one.
+pub async fn top_authors(
+    ctx: &Context,
+    guild_id: GuildId,
+    board: &BoardConfig,
+    author_id: Option<UserId>,
+    limit: i64,
+) -> Result<Vec<AuthorStars>> {
+    let mut filter = doc! {
+        "guild_id": guild_id.to_string(),
+        "board": &board.name,
+    };
+    if let Some(author_id) = author_id {
+        filter.insert("author_id", author_id.to_string());
+    }
+    let stats = get_data::<DbKey>(ctx)
+        .await?
+        .collection::<StarredMessage>(COLLECTION_NAME)
+        .aggregate(vec![
+            doc! { "$match": filter },
+            doc! { "$group": {
+                "_id": "$author_id",
+                "stars": { "$sum": "$count" },
+                "messages": { "$sum": 1 },
+            } },
+            doc! { "$sort": { "stars": -1 } },
+            doc! { "$limit": limit },
+        ])
+        .with_type::<AuthorStars>()
+        .await?
+        .try_collect()
+        .await?;
+    Ok(stats)
+}
+
+/// Messages with the most stars, optionally only of the given member.
+pub async fn top_messages(
+    ctx: &Context,
+    guild_id: GuildId,
+    board: &BoardConfig,
+    author_id: Option<UserId>,
+    limit: i64,
+) -> Result<Vec<StarredMessage>> {
+    let mut filter = doc! {
+        "guild_id": guild_id.to_string(),
+        "board": &board.name,
+    };
+    if let Some(author_id) = author_id {
+        filter.insert("author_id", author_id.to_string());
+    }
+    let messages = get_data::<DbKey>(ctx)
+        .await?
+        .collection::<StarredMessage>(COLLECTION_NAME)
+        .find(filter)
+        .sort(doc! { "count": -1 })
+        .limit(limit)
+        .await?
+        .try_collect()
+        .await?;
+    Ok(messages)
+}
+
+/// Up to `size` random starred messages of the board.
+pub async fn random(
+    ctx: &Context,
+    guild_id: GuildId,
+    board: &BoardConfig,
+    size: u8,
+) -> Result<Vec<StarredMessage>> {
+    let sample = get_data::<DbKey>(ctx)
+        .await?
+        .collection::<StarredMessage>(COLLECTION_NAME)
+        .aggregate(vec![
+            doc! { "$match": {
+                "guild_id": guild_id.to_string(),
+                "board": &board.name,
+            } },
+            doc! { "$sample": { "size": i32::from(size) } },
+        ])
+        .with_type::<StarredMessage>()
+        .await?;
+    Ok(sample.try_collect().await?)
+}
+
+/// Attachments of the message small enough to re-upload.
+pub async fn download_attachments(message: &Message) -> Result<Vec<CreateAttachment>> {
+    let mut attachments = vec![];
+    for attach in &message.attachments {
+        if attach.size < MAX_ATTACH_SIZE {
+            attachments.push(CreateAttachment::bytes(
+                attach.download().await?,
+                attach.filename.clone(),
+            ));
+        }
+    }
+    Ok(attachments)
+}
+
+pub async fn create_embed_from_message(
     ctx: &Context,
     guild: &Guild,
     msg: &Message,
 ) -> Result<CreateEmbed> {
-    let member = guild.member(ctx, msg.author.id).await?;
+    let author = match guild.member(ctx, msg.author.id).await {
+        Ok(member) => CreateEmbedAuthor::new(member.display_name()).icon_url(member.face()),
+        // left the guild since
+        Err(_) => CreateEmbedAuthor::new(msg.author.display_name()).icon_url(msg.author.face()),
+    };
     let mut embed = CreateEmbed::new()
-        .author(CreateEmbedAuthor::new(member.display_name()).icon_url(member.face()))
-        .url(msg.link())
+        .author(author)
+        .url(msg.id.link(msg.channel_id, Some(guild.id)))
         .description(&msg.content)
         .timestamp(msg.timestamp);
     if msg.attachments.len() == 1
@@ -340,3 +992,112 @@ fn guild_roles<'id>(
         .into_iter()
         .filter_map(move |role_id| guild.roles.get(role_id))
 }
+
+/// Whether one of the `ignore_channels` roles can't see the channel.
+fn is_ignored_channel(config: &Config, guild: &Guild, channel: &GuildChannel) -> bool {
+    guild_roles(guild, &config.starboard.ignore_channels).any(|role| {
+        channel.permission_overwrites.iter().any(|overwrite| {
+            overwrite.kind == PermissionOverwriteType::Role(role.id)
+                && overwrite.deny.contains(Permissions::VIEW_CHANNEL)
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_eligible, is_old_enough, star_weight};
+    use crate::config::StarboardConfig;
+    use chrono::{TimeDelta, Utc};
+    use maplit::{hashmap, hashset};
+    use serenity::all::{RoleId, User, UserId};
+
+    fn user(id: u64, bot: bool) -> User {
+        let mut user = User::default();
+        user.id = UserId::new(id);
+        user.bot = bot;
+        user
+    }
+
+    fn config() -> StarboardConfig {
+        StarboardConfig {
+            boards: vec![],
+            emoji: None,
+            threshold: None,
+            max_threshold: None,
+            channels: hashset! {},
+            ignore_stars: hashset! {},
+            ignore_messages: hashset! {},
+            ignore_channels: hashset! {},
+            delete_below_threshold: false,
+            delete_with_original: false,
+            exclude_author: false,
+            exclude_bots: false,
+            catch_up: 0,
+            role_weights: hashmap! {},
+            min_member_age: None,
+            min_messages: None,
+        }
+    }
+
+    #[test]
+    fn eligibility() {
+        let author = UserId::new(1);
+        let ignored = RoleId::new(10);
+        let mut config = config();
+        config.ignore_stars = hashset! { ignored };
+
+        assert!(is_eligible(&config, author, &user(1, false), &[]));
+        assert!(is_eligible(&config, author, &user(2, true), &[]));
+        assert!(is_eligible(
+            &config,
+            author,
+            &user(2, false),
+            &[RoleId::new(11)]
+        ));
+        assert!(!is_eligible(
+            &config,
+            author,
+            &user(2, false),
+            &[RoleId::new(11), ignored]
+        ));
+
+        config.exclude_author = true;
+        config.exclude_bots = true;
+        assert!(!is_eligible(&config, author, &user(1, false), &[]));
+        assert!(!is_eligible(&config, author, &user(2, true), &[]));
+        assert!(is_eligible(&config, author, &user(2, false), &[]));
+    }
+
+    #[test]
+    fn weights() {
+        let trusted = RoleId::new(10);
+        let new = RoleId::new(11);
+        let mut config = config();
+        assert_eq!(star_weight(&config, &[trusted]), 1);
+
+        config.role_weights = hashmap! { trusted => 3, new => 0 };
+        assert_eq!(star_weight(&config, &[]), 1);
+        assert_eq!(star_weight(&config, &[RoleId::new(12)]), 1);
+        assert_eq!(star_weight(&config, &[new]), 0);
+        assert_eq!(star_weight(&config, &[new, trusted]), 3);
+    }
+
+    #[test]
+    fn member_age() {
+        let now = Utc::now();
+        let mut config = config();
+        assert!(is_old_enough(&config, None, now));
+
+        config.min_member_age = Some(24 * 60 * 60);
+        assert!(!is_old_enough(&config, None, now));
+        assert!(!is_old_enough(
+            &config,
+            Some(now - TimeDelta::hours(1)),
+            now
+        ));
+        assert!(is_old_enough(&config, Some(now - TimeDelta::days(2)), now));
+
+        config.min_member_age = Some(u64::MAX);
+        assert!(!is_old_enough(&config, Some(now - TimeDelta::days(2)), now));
+    }
+}
@@ -3,8 +3,10 @@ use chrono::{DateTime, Utc};
 use color_eyre::eyre::{Result, eyre};
 use conv::{UnwrapOrSaturate, ValueFrom};
 use lazy_regex::regex;
-use mongodb::bson::doc;
+use mongodb::bson::{doc, serde_helpers::datetime::FromChrono04DateTime};
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "starboard")]
+use serenity::model::id::GuildId;
 use serenity::{
     client::Context,
     model::{
@@ -25,7 +27,9 @@ pub enum Stats {
         tags: Vec<String>,
         nick: String,
         nicks: Vec<String>,
+        #[serde(with = "FromChrono04DateTime")]
         first_message: DateTime<Utc>,
+        #[serde(with = "FromChrono04DateTime")]
         last_message: DateTime<Utc>,
         emoji_count: usize,
         message_count: usize,
@@ -38,7 +42,9 @@ pub enum Stats {
         id: String,
         name: String,
         names: Vec<String>,
+        #[serde(with = "FromChrono04DateTime")]
         first_message: DateTime<Utc>,
+        #[serde(with = "FromChrono04DateTime")]
         last_message: DateTime<Utc>,
         emoji_count: usize,
         message_count: usize,
@@ -51,12 +57,36 @@ pub enum Stats {
         id: String,
         name: String,
         names: Vec<String>,
+        #[serde(with = "FromChrono04DateTime")]
         first_message: DateTime<Utc>,
+        #[serde(with = "FromChrono04DateTime")]
         last_message: DateTime<Utc>,
         use_count: usize,
     },
 }
 
+/// How many messages the member has sent in the guild, as far as we've seen.
+#[cfg(feature = "starboard")]
+pub async fn member_message_count(
+    ctx: &Context,
+    guild_id: GuildId,
+    user_id: UserId,
+) -> Result<usize> {
+    let stats = get_data::<DbKey>(ctx)
+        .await?
+        .collection::<Stats>(COLLECTION_NAME)
+        .find_one(doc! {
+            "type": "member",
+            "id": user_id.to_string(),
+            "guild_id": guild_id.to_string(),
+        })
+        .await?;
+    Ok(match stats {
+        Some(Stats::Member { message_count, .. }) => message_count,
+        _ => 0,
+    })
+}
+
 #[allow(clippy::too_many_lines)]
 pub async fn update_stats(ctx: &Context, msg: &Message) -> Result<()> {
     let channel = msg
@@ -1,16 +1,76 @@
-use super::{DbKey, get_data};
+use super::{ConfigKey, DbKey, get_data, spawn_once};
+use crate::config::StickyRolesConfig;
+use chrono::{DateTime, TimeDelta, Utc};
 use color_eyre::eyre::{Result, eyre};
+use futures::{StreamExt, TryStreamExt};
 use itertools::Itertools;
-use log::info;
-use mongodb::bson::{Document, doc};
+use log::{error, info, warn};
+use mongodb::{
+    bson::{Document, doc},
+    error::ErrorKind,
+    options::UpdateOneModel,
+};
 use serenity::{
-    all::EditMember,
+    all::{EditMember, Timestamp},
     client::Context,
-    model::{guild::Member, id::RoleId},
+    model::{
+        guild::Member,
+        id::{GuildId, RoleId, UserId},
+    },
+    prelude::TypeMapKey,
 };
-use std::collections::HashSet;
+use std::{collections::HashSet, time::Duration};
 
 const COLLECTION_NAME: &str = "sticky-roles";
+/// Members per bulk upsert, matching the page size of the member list endpoint.
+const SNAPSHOT_CHUNK: usize = 1000;
+/// Upserts in flight at once when bulk writes aren't supported.
+const UPSERT_CONCURRENCY: usize = 16;
+
+#[derive(Debug)]
+struct ReconcilerKey;
+
+impl TypeMapKey for ReconcilerKey {
+    type Value = ();
+}
+
+fn should_restore(config: Option<&StickyRolesConfig>, role_id: RoleId) -> bool {
+    let Some(config) = config else {
+        return true;
+    };
+    config.always.contains(&role_id)
+        || (config
+            .allow
+            .as_ref()
+            .is_none_or(|allow| allow.contains(&role_id))
+            && !config.deny.contains(&role_id))
+}
+
+fn is_expired(
+    config: Option<&StickyRolesConfig>,
+    left_at: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> bool {
+    let (Some(days), Some(left_at)) = (config.and_then(|config| config.expiry), left_at) else {
+        return false;
+    };
+    i64::try_from(days).is_ok_and(|days| now - left_at > TimeDelta::days(days))
+}
+
+fn sticky_filter(guild_id: GuildId, user_id: UserId) -> Document {
+    doc! {
+        "user_id": user_id.to_string(),
+        "guild_id": guild_id.to_string(),
+    }
+}
+
+fn sticky_fields(member: &Member) -> Document {
+    doc! {
+        "role_ids": member.roles.iter().map(ToString::to_string).collect::<Vec<_>>(),
+        "nick": member.nick.as_deref(),
+        "timeout_until": member.communication_disabled_until.map(|until| *until),
+    }
+}
 
 pub async fn save_stickies(ctx: &Context, member: &Member) -> Result<()> {
     let collection = get_data::<DbKey>(ctx)
@@ -18,77 +78,390 @@ pub async fn save_stickies(ctx: &Context, member: &Member) -> Result<()> {
         .collection::<Document>(COLLECTION_NAME);
     collection
         .update_one(
+            sticky_filter(member.guild_id, member.user.id),
+            sticky_update(member),
+        )
+        .upsert(true)
+        .await?;
+    Ok(())
+}
+
+fn sticky_update(member: &Member) -> Document {
+    doc! {
+        "$set": sticky_fields(member),
+        "$unset": { "left_at": "" },
+    }
+}
+
+/// Upserts the stickies of all given members in a single round trip.
+/// Servers before MongoDB 8.0 don't support that, so they get concurrent upserts instead.
+async fn save_stickies_bulk(db: &mongodb::Database, members: &[Member]) -> Result<()> {
+    if members.is_empty() {
+        return Ok(());
+    }
+    let collection = db.collection::<Document>(COLLECTION_NAME);
+    let updates: Vec<(Document, Document)> = members
+        .iter()
+        .map(|member| {
+            (
+                sticky_filter(member.guild_id, member.user.id),
+                sticky_update(member),
+            )
+        })
+        .collect();
+
+    let namespace = collection.namespace();
+    let models = updates.iter().map(|(filter, update)| {
+        UpdateOneModel::builder()
+            .namespace(namespace.clone())
+            .filter(filter.clone())
+            .update(update.clone())
+            .upsert(true)
+            .build()
+    });
+    let failed: Vec<String> = match db.client().bulk_write(models).ordered(false).await {
+        Ok(_) => vec![],
+        Err(err) => match *err.kind {
+            ErrorKind::IncompatibleServer { .. } => {
+                futures::stream::iter(updates)
+                    .map(|(filter, update)| {
+                        collection
+                            .update_one(filter, update)
+                            .upsert(true)
+                            .into_future()
+                    })
+                    .buffer_unordered(UPSERT_CONCURRENCY)
+                    .filter_map(|result| async move { result.err().map(|err| format!("{err:?}")) })
+                    .collect()
+                    .await
+            }
+            ErrorKind::BulkWrite(ref bulk) if bulk.write_concern_errors.is_empty() => bulk
+                .write_errors
+                .values()
+                .map(|err| format!("{err:?}"))
+                .collect(),
+            _ => return Err(err.into()),
+        },
+    };
+    if !failed.is_empty() {
+        warn!("{} sticky role upserts failed: {failed:?}", failed.len());
+    }
+    Ok(())
+}
+
+/// Saves the stickies of every member of the guild.
+pub async fn snapshot(ctx: &Context, guild_id: GuildId) -> Result<()> {
+    let db = get_data::<DbKey>(ctx).await?;
+    let mut chunks = std::pin::pin!(guild_id.members_iter(ctx).try_chunks(SNAPSHOT_CHUNK));
+    let mut seen: Vec<String> = vec![];
+    while let Some(members) = chunks.try_next().await.map_err(|err| err.1)? {
+        save_stickies_bulk(&db, &members).await?;
+        seen.extend(members.iter().map(|member| member.user.id.to_string()));
+    }
+    info!(
+        "Snapshotted stickies of {} members in {guild_id}",
+        seen.len()
+    );
+
+    // members who left while we were offline, so their stickies expire too
+    let result = db
+        .collection::<Document>(COLLECTION_NAME)
+        .update_many(
             doc! {
-                "user_id": member.user.id.to_string(),
-                "guild_id": member.guild_id.to_string(),
-            },
-            doc! {
-                "$set": {
-                    "role_ids": member.roles.iter().map(ToString::to_string).collect::<Vec<_>>(),
-                },
+                "guild_id": guild_id.to_string(),
+                "user_id": { "$nin": seen },
+                "left_at": { "$exists": false },
             },
+            doc! { "$set": { "left_at": Utc::now() } },
         )
-        .upsert(true)
         .await?;
+    if result.modified_count > 0 {
+        info!(
+            "Marked stickies of {} members who left {guild_id} as left",
+            result.modified_count
+        );
+    }
     Ok(())
 }
 
-pub async fn apply_stickies(ctx: &Context, member: &mut Member) -> Result<bool> {
+/// Periodically snapshots all guilds to catch member updates missed while disconnected.
+pub async fn spawn_reconciler(ctx: &Context) -> Result<()> {
+    let interval = get_data::<ConfigKey>(ctx)
+        .await?
+        .discord
+        .sticky_roles_reconcile_interval;
+    if interval == 0 {
+        return Ok(());
+    }
+    spawn_once::<ReconcilerKey, _, _>(ctx, Duration::from_secs(interval), |ctx| async move {
+        for guild_id in ctx.cache.guilds() {
+            if let Err(err) = snapshot(&ctx, guild_id).await {
+                error!("Unable to reconcile stickies for {guild_id}: {err:?}");
+            }
+        }
+    })
+    .await;
+    Ok(())
+}
+
+/// Marks the member's stickies as belonging to someone who left, for expiry.
+pub async fn mark_left(ctx: &Context, guild_id: GuildId, user_id: UserId) -> Result<()> {
     let collection = get_data::<DbKey>(ctx)
         .await?
         .collection::<Document>(COLLECTION_NAME);
-    if let Some(entry) = collection
-        .find_one(doc! {
-            "user_id": member.user.id.to_string(),
-            "guild_id": member.guild_id.to_string(),
-        })
-        .projection(doc! { "role_ids": 1 })
-        .await?
-    {
-        let current_user_id = ctx.cache.current_user().id;
-
-        let guild = member
-            .guild_id
-            .to_guild_cached(ctx)
-            .ok_or_else(|| eyre!("Guild not found!"))?
-            .clone();
-
-        let bot_roles: HashSet<RoleId> = guild
-            .member(&ctx, current_user_id)
-            .await?
-            .roles(ctx)
-            .ok_or_else(|| eyre!("Roles for bot not found!"))?
-            .into_iter()
-            .map(|role| role.id)
-            .collect();
-        let guild_role_ids: HashSet<RoleId> = guild
-            .roles
-            .values()
-            .sorted_by_key(|role| role.position)
-            .rev()
-            .take_while(|role| !bot_roles.contains(&role.id))
-            .map(|role| role.id)
-            .collect();
+    collection
+        .update_one(
+            sticky_filter(guild_id, user_id),
+            doc! { "$set": { "left_at": Utc::now() } },
+        )
+        .await?;
+    Ok(())
+}
+
+/// The stored stickies of a member.
+#[derive(Debug, Clone)]
+pub struct Stickies {
+    pub role_ids: Vec<RoleId>,
+    pub nick: Option<String>,
+    pub timeout_until: Option<DateTime<Utc>>,
+    pub left_at: Option<DateTime<Utc>>,
+}
 
+impl Stickies {
+    fn from_document(entry: &Document) -> Result<Self> {
         #[allow(clippy::cast_sign_loss)]
-        let role_ids: Vec<RoleId> = entry
+        let role_ids = entry
             .get_array("role_ids")?
             .iter()
             .filter_map(|i| i.as_str().and_then(|s| s.parse().ok()).map(RoleId::new))
-            .filter(|id| guild_role_ids.contains(id))
             .collect();
+        Ok(Self {
+            role_ids,
+            nick: entry.get_str("nick").ok().map(ToOwned::to_owned),
+            timeout_until: entry
+                .get_datetime("timeout_until")
+                .ok()
+                .map(|dt| dt.to_chrono()),
+            left_at: entry.get_datetime("left_at").ok().map(|dt| dt.to_chrono()),
+        })
+    }
 
-        if !role_ids.is_empty() {
-            info!("Restoring roles: {role_ids:?}");
+    /// Whether these stickies are too old to be restored under the guild's policy.
+    #[inline]
+    pub fn is_expired(&self, config: Option<&StickyRolesConfig>) -> bool {
+        is_expired(config, self.left_at, Utc::now())
+    }
+}
 
-            let mut user_role_ids: Vec<RoleId> = member.roles.clone();
-            user_role_ids.extend(role_ids);
-            member
-                .edit(&ctx, EditMember::new().roles(user_role_ids))
-                .await?;
+pub async fn get_stickies(
+    ctx: &Context,
+    guild_id: GuildId,
+    user_id: UserId,
+) -> Result<Option<Stickies>> {
+    let collection = get_data::<DbKey>(ctx)
+        .await?
+        .collection::<Document>(COLLECTION_NAME);
+    collection
+        .find_one(sticky_filter(guild_id, user_id))
+        .await?
+        .map(|entry| Stickies::from_document(&entry))
+        .transpose()
+}
 
-            return Ok(true);
+/// Deletes the stored stickies of a member, returning whether there were any.
+pub async fn clear_stickies(ctx: &Context, guild_id: GuildId, user_id: UserId) -> Result<bool> {
+    let collection = get_data::<DbKey>(ctx)
+        .await?
+        .collection::<Document>(COLLECTION_NAME);
+    let result = collection
+        .delete_one(sticky_filter(guild_id, user_id))
+        .await?;
+    Ok(result.deleted_count > 0)
+}
+
+/// Removes a role from the stored stickies of a member, returning whether it was there.
+pub async fn remove_sticky(
+    ctx: &Context,
+    guild_id: GuildId,
+    user_id: UserId,
+    role_id: RoleId,
+) -> Result<bool> {
+    let collection = get_data::<DbKey>(ctx)
+        .await?
+        .collection::<Document>(COLLECTION_NAME);
+    let result = collection
+        .update_one(
+            sticky_filter(guild_id, user_id),
+            doc! { "$pull": { "role_ids": role_id.to_string() } },
+        )
+        .await?;
+    Ok(result.modified_count > 0)
+}
+
+/// What was restored to a returning member.
+#[derive(Debug, Clone, Default)]
+pub struct Restored {
+    pub role_ids: Vec<RoleId>,
+    pub nick: Option<String>,
+    /// A timeout that was still running when the member left.
+    pub timeout_until: Option<DateTime<Utc>>,
+}
+
+impl Restored {
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.role_ids.is_empty() && self.nick.is_none() && self.timeout_until.is_none()
+    }
+}
+
+pub async fn apply_stickies(ctx: &Context, member: &mut Member) -> Result<Restored> {
+    let Some(stickies) = get_stickies(ctx, member.guild_id, member.user.id).await? else {
+        return Ok(Restored::default());
+    };
+    let config = get_data::<ConfigKey>(ctx).await?;
+    let sticky_config = config.discord.sticky_roles.get(&member.guild_id);
+    if stickies.is_expired(sticky_config) {
+        info!("Not restoring expired {stickies:?}");
+        // otherwise leaving again would refresh `left_at` and revive them
+        clear_stickies(ctx, member.guild_id, member.user.id).await?;
+        return Ok(Restored::default());
+    }
+
+    let current_user_id = ctx.cache.current_user().id;
+
+    let guild = member
+        .guild_id
+        .to_guild_cached(ctx)
+        .ok_or_else(|| eyre!("Guild not found!"))?
+        .clone();
+
+    let bot_roles: HashSet<RoleId> = guild
+        .member(&ctx, current_user_id)
+        .await?
+        .roles(ctx)
+        .ok_or_else(|| eyre!("Roles for bot not found!"))?
+        .into_iter()
+        .map(|role| role.id)
+        .collect();
+    let guild_role_ids: HashSet<RoleId> = guild
+        .roles
+        .values()
+        .sorted_by_key(|role| role.position)
+        .rev()
+        .take_while(|role| !bot_roles.contains(&role.id))
+        .map(|role| role.id)
+        .collect();
+
+    let role_ids: Vec<RoleId> = stickies
+        .role_ids
+        .into_iter()
+        .filter(|id| guild_role_ids.contains(id) && should_restore(sticky_config, *id))
+        .collect();
+
+    let nick = stickies.nick.filter(|nick| {
+        sticky_config.is_some_and(|sticky_config| sticky_config.restore_nickname)
+            && member.nick.is_none()
+            && !nick.is_empty()
+    });
+
+    let timeout_until = stickies
+        .timeout_until
+        .filter(|until| *until > Utc::now() && member.communication_disabled_until.is_none());
+
+    let mut restored = Restored {
+        role_ids,
+        nick,
+        timeout_until,
+    };
+    if !restored.is_empty() {
+        info!("Restoring {restored:?}");
+    }
+
+    if !restored.role_ids.is_empty() {
+        let mut user_role_ids: Vec<RoleId> = member.roles.clone();
+        user_role_ids.extend(&restored.role_ids);
+        member
+            .edit(&ctx, EditMember::new().roles(user_role_ids))
+            .await?;
+    }
+    // the nick and timeout need other permissions, which shouldn't stand in the way of the roles
+    if let Some(ref nick) = restored.nick {
+        if let Err(err) = member.edit(&ctx, EditMember::new().nickname(nick)).await {
+            error!(
+                "Unable to restore nickname of {} in {}: {err:?}",
+                member.user.id, member.guild_id
+            );
+            restored.nick = None;
+        }
+    }
+    if let Some(until) = restored.timeout_until {
+        let edit = EditMember::new().disable_communication_until_datetime(Timestamp::from(until));
+        if let Err(err) = member.edit(&ctx, edit).await {
+            error!(
+                "Unable to restore timeout of {} in {}: {err:?}",
+                member.user.id, member.guild_id
+            );
+            restored.timeout_until = None;
         }
     }
-    Ok(false)
+    Ok(restored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_expired, should_restore};
+    use crate::config::StickyRolesConfig;
+    use chrono::{TimeDelta, Utc};
+    use maplit::hashset;
+    use serenity::all::RoleId;
+
+    #[test]
+    fn restore_policy() {
+        let (a, b, mute) = (RoleId::new(1), RoleId::new(2), RoleId::new(3));
+
+        assert!(should_restore(None, a));
+        assert!(should_restore(Some(&StickyRolesConfig::default()), a));
+
+        let config = StickyRolesConfig {
+            allow: Some(hashset! { a }),
+            always: hashset! { mute },
+            ..Default::default()
+        };
+        assert!(should_restore(Some(&config), a));
+        assert!(!should_restore(Some(&config), b));
+        assert!(should_restore(Some(&config), mute));
+
+        let config = StickyRolesConfig {
+            deny: hashset! { b, mute },
+            always: hashset! { mute },
+            ..Default::default()
+        };
+        assert!(should_restore(Some(&config), a));
+        assert!(!should_restore(Some(&config), b));
+        assert!(should_restore(Some(&config), mute));
+    }
+
+    #[test]
+    fn expiry() {
+        let now = Utc::now();
+        let left_at = Some(now - TimeDelta::days(10));
+        let config = StickyRolesConfig {
+            expiry: Some(7),
+            ..Default::default()
+        };
+
+        assert!(!is_expired(None, left_at, now));
+        assert!(!is_expired(
+            Some(&StickyRolesConfig::default()),
+            left_at,
+            now
+        ));
+        assert!(!is_expired(Some(&config), None, now));
+        assert!(is_expired(Some(&config), left_at, now));
+        assert!(!is_expired(
+            Some(&config),
+            Some(now - TimeDelta::days(6)),
+            now
+        ));
+    }
 }
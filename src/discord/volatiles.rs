@@ -1,11 +1,191 @@
-use super::{ConfigKey, DbKey, get_data};
-use bson::doc;
-use chrono::Utc;
+use super::{ConfigKey, DbKey, get_data, spawn_once};
+use crate::{
+    config::{Config, VolatileConfig},
+    util::try_seconds,
+};
+use bson::{Document, doc};
+use chrono::{DateTime, TimeDelta, Utc};
 use color_eyre::eyre::Result;
-use serenity::all::{Context, Message};
+use futures::TryStreamExt;
+use serenity::{
+    all::{ChannelId, Context, GuildId, Message, UserId},
+    http::StatusCode,
+    prelude::TypeMapKey,
+};
+use std::time::Duration;
 use tokio::join;
 
 pub const COLLECTION_NAME: &str = "volatiles";
+/// How long records are kept after their window ended.
+pub const EXPIRY_GRACE: TimeDelta = TimeDelta::days(7);
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+struct SweeperKey;
+
+impl TypeMapKey for SweeperKey {
+    type Value = ();
+}
+
+/// When the window that started at `time` ends, if ever.
+fn window_end(config: &VolatileConfig, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    config
+        .window
+        .and_then(try_seconds)
+        .and_then(|window| time.checked_add_signed(window))
+}
+
+/// Number of posts already made in the current window, given the stored window.
+fn posts_in_window(
+    config: &VolatileConfig,
+    stored: Option<(DateTime<Utc>, u64)>,
+    now: DateTime<Utc>,
+) -> u64 {
+    match stored {
+        Some((time, count)) if window_end(config, time).is_none_or(|end| end > now) => count,
+        _ => 0,
+    }
+}
+
+/// A user's record in a volatile channel.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub channel_id: ChannelId,
+    pub user_id: UserId,
+    pub name: String,
+    /// Start of the current window.
+    pub time: DateTime<Utc>,
+    pub count: u64,
+    /// End of the current window, if it ends and the channel is still volatile.
+    pub window_end: Option<DateTime<Utc>>,
+}
+
+impl Entry {
+    fn from_document(volatiles: &[VolatileConfig], entry: &Document) -> Result<Self> {
+        let channel_id = ChannelId::new(entry.get_document("channel")?.get_str("id")?.parse()?);
+        let user = entry.get_document("user")?;
+        let time = entry.get_datetime("time")?.to_chrono();
+        Ok(Self {
+            channel_id,
+            user_id: UserId::new(user.get_str("id")?.parse()?),
+            name: user.get_str("nick").unwrap_or_default().to_owned(),
+            time,
+            count: entry
+                .get_i64("count")
+                .ok()
+                .and_then(|count| u64::try_from(count).ok())
+                .unwrap_or(1),
+            window_end: volatiles
+                .iter()
+                .find(|volatile| volatile.channel == channel_id)
+                .and_then(|volatile| window_end(volatile, time)),
+        })
+    }
+
+    /// Whether the window has ended, so the user may post again.
+    #[inline]
+    pub fn has_reset(&self) -> bool {
+        self.window_end.is_some_and(|end| end <= Utc::now())
+    }
+}
+
+async fn find(ctx: &Context, filter: Document) -> Result<Vec<Entry>> {
+    let config = get_data::<ConfigKey>(ctx).await?;
+    let collection = get_data::<DbKey>(ctx)
+        .await?
+        .collection::<Document>(COLLECTION_NAME);
+    let documents: Vec<Document> = collection
+        .find(filter)
+        .sort(doc! { "time": -1 })
+        .await?
+        .try_collect()
+        .await?;
+    documents
+        .iter()
+        .map(|entry| Entry::from_document(&config.discord.volatiles, entry))
+        .collect()
+}
+
+/// Everyone who posted in the channel, most recent first.
+pub async fn list(ctx: &Context, channel_id: ChannelId) -> Result<Vec<Entry>> {
+    find(ctx, doc! { "channel.id": channel_id.to_string() }).await
+}
+
+/// Whether the channel is one of the guild's volatile channels.
+pub fn is_volatile(
+    ctx: &Context,
+    config: &Config,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+) -> bool {
+    config
+        .discord
+        .volatiles
+        .iter()
+        .any(|volatile| volatile.channel == channel_id)
+        && ctx
+            .cache
+            .guild(guild_id)
+            .is_some_and(|guild| guild.channels.contains_key(&channel_id))
+}
+
+/// The user's records in the guild's volatile channels, most recent first.
+pub async fn status(ctx: &Context, guild_id: GuildId, user_id: UserId) -> Result<Vec<Entry>> {
+    let config = get_data::<ConfigKey>(ctx).await?;
+    let channel_ids: Vec<String> = config
+        .discord
+        .volatiles
+        .iter()
+        .filter(|volatile| is_volatile(ctx, &config, guild_id, volatile.channel))
+        .map(|volatile| volatile.channel.to_string())
+        .collect();
+    find(
+        ctx,
+        doc! {
+            "channel.id": { "$in": channel_ids },
+            "user.id": user_id.to_string(),
+        },
+    )
+    .await
+}
+
+/// Lets the user post in the channel again, returning whether they had a record.
+pub async fn reset(
+    ctx: &Context,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    user_id: UserId,
+) -> Result<bool> {
+    let config = get_data::<ConfigKey>(ctx).await?;
+    let collection = get_data::<DbKey>(ctx)
+        .await?
+        .collection::<Document>(COLLECTION_NAME);
+    let result = collection
+        .delete_one(doc! {
+            "channel.id": channel_id.to_string(),
+            "user.id": user_id.to_string(),
+        })
+        .await?;
+
+    if let Some(volatile) = config
+        .discord
+        .volatiles
+        .iter()
+        .find(|volatile| volatile.channel == channel_id)
+    {
+        match ctx
+            .http
+            .remove_member_role(guild_id, user_id, volatile.role, Some("Volatile reset"))
+            .await
+        {
+            Ok(()) => {}
+            Err(serenity::Error::Http(err)) if err.status_code() == Some(StatusCode::NOT_FOUND) => {
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(result.deleted_count > 0)
+}
 
 pub async fn enforce(ctx: &Context, msg: &Message) -> Result<()> {
     let config = get_data::<ConfigKey>(ctx).await?;
@@ -20,16 +200,31 @@ pub async fn enforce(ctx: &Context, msg: &Message) -> Result<()> {
     };
 
     let member = msg.member(ctx).await?;
-    let collection = get_data::<DbKey>(ctx).await?.collection(COLLECTION_NAME);
+    let collection = get_data::<DbKey>(ctx)
+        .await?
+        .collection::<Document>(COLLECTION_NAME);
+    let filter = doc! {
+        "channel.id": msg.channel_id.to_string(),
+        "user.id": msg.author.id.to_string()
+    };
 
-    if collection
-        .find_one(doc! {
-            "channel.id": msg.channel_id.to_string(),
-            "user.id": msg.author.id.to_string()
-        })
+    let now = Utc::now();
+    let stored = collection
+        .find_one(filter.clone())
         .await?
-        .is_some()
-    {
+        .and_then(|entry| {
+            let time = entry.get_datetime("time").ok()?.to_chrono();
+            // records from before windows existed only count the first post
+            let count = entry
+                .get_i64("count")
+                .ok()
+                .and_then(|count| u64::try_from(count).ok())
+                .unwrap_or(1);
+            Some((time, count))
+        });
+    let posts = posts_in_window(config, stored, now);
+
+    if posts >= config.posts {
         log::info!("Repeat volatile message from {}", member.display_name());
         let (delete_result, add_result) = join!(msg.delete(ctx), member.add_role(ctx, config.role));
         delete_result?;
@@ -37,25 +232,150 @@ pub async fn enforce(ctx: &Context, msg: &Message) -> Result<()> {
         return Ok(());
     }
 
-    log::info!("First volatile message from {}", member.display_name());
-    let (add_result, db_result) = join!(
-        member.add_role(ctx, config.role),
-        collection
-            .insert_one(doc! {
-                "time": Utc::now(),
-                "channel": {
-                    "id": msg.channel_id.to_string(),
-                },
+    let update = if posts == 0 {
+        log::info!("First volatile message from {}", member.display_name());
+        doc! {
+            "$set": {
+                "time": now,
+                "count": 1_i64,
+                "expires_at": window_end(config, now).and_then(|end| end.checked_add_signed(EXPIRY_GRACE)),
                 "user": {
                     "id": msg.author.id.to_string(),
                     "name": &msg.author.name,
                     "nick": member.display_name()
                 }
-            })
-            .into_future(),
-    );
-    add_result?;
+            },
+            "$unset": { "reset": "" },
+        }
+    } else {
+        log::info!(
+            "Volatile message {} from {}",
+            posts + 1,
+            member.display_name()
+        );
+        doc! { "$inc": { "count": 1_i64 } }
+    };
+    let db_result = collection.update_one(filter, update).upsert(true).await;
+    if posts + 1 >= config.posts {
+        member.add_role(ctx, config.role).await?;
+    }
     db_result?;
 
     Ok(())
 }
+
+/// Removes the role from everyone whose window has ended.
+async fn sweep(ctx: &Context) -> Result<()> {
+    let config = get_data::<ConfigKey>(ctx).await?;
+    let collection = get_data::<DbKey>(ctx)
+        .await?
+        .collection::<Document>(COLLECTION_NAME);
+    let now = Utc::now();
+
+    for volatile in &config.discord.volatiles {
+        // windows too long to ever end
+        let Some(started_before) = volatile
+            .window
+            .and_then(try_seconds)
+            .and_then(|window| now.checked_sub_signed(window))
+        else {
+            continue;
+        };
+        let channel = match volatile.channel.to_channel(ctx).await {
+            Ok(channel) => channel,
+            Err(err) => {
+                log::error!(
+                    "Unable to sweep volatile channel {}: {err:?}",
+                    volatile.channel
+                );
+                continue;
+            }
+        };
+        let Some(guild_id) = channel.guild().map(|channel| channel.guild_id) else {
+            continue;
+        };
+
+        let filter = doc! {
+            "channel.id": volatile.channel.to_string(),
+            "time": { "$lte": started_before },
+            "reset": { "$ne": true },
+        };
+        let ended: Vec<Document> = collection.find(filter).await?.try_collect().await?;
+        for entry in ended {
+            let user_id = UserId::new(entry.get_document("user")?.get_str("id")?.parse()?);
+            match ctx
+                .http
+                .remove_member_role(
+                    guild_id,
+                    user_id,
+                    volatile.role,
+                    Some("Volatile window reset"),
+                )
+                .await
+            {
+                Ok(()) => log::info!("Volatile window reset for {user_id}"),
+                Err(serenity::Error::Http(err))
+                    if err.status_code() == Some(StatusCode::NOT_FOUND) => {}
+                // retrying every sweep won't help, so give up on it like on members who left
+                Err(serenity::Error::Http(err))
+                    if err.status_code() == Some(StatusCode::FORBIDDEN) =>
+                {
+                    log::error!(
+                        "Not allowed to reset volatile window for {user_id} in {guild_id}: {err:?}"
+                    );
+                }
+                Err(err) => {
+                    log::error!("Unable to reset volatile window for {user_id}: {err:?}");
+                    continue;
+                }
+            }
+            collection
+                .update_one(
+                    doc! { "_id": entry.get_object_id("_id")? },
+                    doc! { "$set": { "reset": true } },
+                )
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Periodically resets the volatile windows that have ended.
+pub async fn spawn_sweeper(ctx: &Context) {
+    spawn_once::<SweeperKey, _, _>(ctx, SWEEP_INTERVAL, |ctx| async move {
+        if let Err(err) = sweep(&ctx).await {
+            log::error!("Unable to sweep volatiles: {err:?}");
+        }
+    })
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::posts_in_window;
+    use crate::config::VolatileConfig;
+    use chrono::{TimeDelta, Utc};
+    use serenity::all::{ChannelId, RoleId};
+
+    #[test]
+    fn windows() {
+        let now = Utc::now();
+        let mut config = VolatileConfig {
+            channel: ChannelId::new(1),
+            role: RoleId::new(2),
+            window: None,
+            posts: 1,
+        };
+        let day_ago = now - TimeDelta::days(1);
+
+        assert_eq!(posts_in_window(&config, None, now), 0);
+        assert_eq!(posts_in_window(&config, Some((day_ago, 1)), now), 1);
+
+        config.window = Some(60 * 60);
+        assert_eq!(posts_in_window(&config, Some((day_ago, 1)), now), 0);
+        assert_eq!(
+            posts_in_window(&config, Some((now - TimeDelta::minutes(5), 3)), now),
+            3
+        );
+    }
+}
@@ -44,7 +44,7 @@ async fn main() -> Result<()> {
 
     let mongo_client = mongodb::Client::with_uri_str(&config.mongodb.uri).await?;
     let db = mongo_client.database(config.mongodb.database.as_ref());
-    migrations::mongo(&db).await?;
+    migrations::mongo(&db, &config).await?;
 
     #[cfg(feature = "openai")]
     #[cfg(feature = "teamup")]
@@ -1,3 +1,4 @@
+use crate::{config::Config, discord::volatiles, util::try_seconds};
 use color_eyre::eyre::Result;
 use futures::TryStreamExt;
 use log::info;
@@ -8,6 +9,7 @@ use mongodb::{
     options::IndexOptions,
     results::UpdateResult,
 };
+use std::time::Duration;
 
 async fn mongo_number_to_string(
     db: &Database,
@@ -50,6 +52,22 @@ async fn mongo_ensure_indexes(
     db: &Database,
     collection: &str,
     indexes: Vec<(Document, bool)>,
+) -> Result<()> {
+    mongo_ensure_indexes_with_options(
+        db,
+        collection,
+        indexes
+            .into_iter()
+            .map(|(spec, unique)| (spec, IndexOptions::builder().unique(unique).build()))
+            .collect(),
+    )
+    .await
+}
+
+async fn mongo_ensure_indexes_with_options(
+    db: &Database,
+    collection: &str,
+    indexes: Vec<(Document, IndexOptions)>,
 ) -> Result<()> {
     // ignore error: the collection might already exist
     let _ = db.create_collection(collection).await;
@@ -57,7 +75,7 @@ async fn mongo_ensure_indexes(
 
     let existing: Vec<_> = collection.list_indexes().await?.try_collect().await?;
 
-    for (spec, unique) in &indexes {
+    for (spec, options) in &indexes {
         if existing.iter().any(|i| &i.keys == spec) {
             continue;
         }
@@ -66,7 +84,7 @@ async fn mongo_ensure_indexes(
             .create_index(
                 IndexModel::builder()
                     .keys(spec.clone())
-                    .options(IndexOptions::builder().unique(*unique).build())
+                    .options(options.clone())
                     .build(),
             )
             .await?;
@@ -87,12 +105,50 @@ async fn mongo_ensure_indexes(
     Ok(())
 }
 
-pub async fn mongo(db: &Database) -> Result<()> {
+#[allow(clippy::too_many_lines)]
+pub async fn mongo(db: &Database, config: &Config) -> Result<()> {
     info!("Running MongoDB migrations...");
     mongo_number_to_string(db, "stats", "id").await?;
     mongo_number_to_string(db, "sticky-roles", "user_id").await?;
     mongo_number_to_string(db, "sticky-roles", "guild_id").await?;
     mongo_number_array_to_string_array(db, "sticky-roles", "role_ids").await?;
+    for volatile in &config.discord.volatiles {
+        // records from before they expired, which the TTL index would never remove
+        let Some(lifetime) = volatile
+            .window
+            .and_then(try_seconds)
+            .and_then(|window| window.checked_add(&volatiles::EXPIRY_GRACE))
+        else {
+            continue;
+        };
+        db.collection::<Document>("volatiles")
+            .update_many(
+                doc! {
+                    "channel.id": volatile.channel.to_string(),
+                    "expires_at": { "$exists": false },
+                },
+                vec![doc! {
+                    "$set": {
+                        "expires_at": { "$dateAdd": {
+                            "startDate": "$time",
+                            "unit": "second",
+                            "amount": lifetime.num_seconds(),
+                        } }
+                    }
+                }],
+            )
+            .await?;
+    }
+    #[cfg(feature = "starboard")]
+    if let Some(board) = config.starboard.boards.first() {
+        // entries from before there were multiple boards
+        db.collection::<Document>("starboard")
+            .update_many(
+                doc! { "board": { "$exists": false } },
+                doc! { "$set": { "board": &board.name } },
+            )
+            .await?;
+    }
 
     info!("Building MongoDB indexes...");
     mongo_ensure_indexes(
@@ -135,18 +191,92 @@ pub async fn mongo(db: &Database) -> Result<()> {
     .await?;
     mongo_ensure_indexes(db, "openai-thinkers", vec![(doc! { "user.id": 1 }, false)]).await?;
 
-    mongo_ensure_indexes(
+    mongo_ensure_indexes_with_options(
         db,
         "volatiles",
         vec![
-            (doc! { "channel.id": 1 }, false),
-            (doc! { "channel.id": 1, "user.id": 1 }, true),
+            (doc! { "channel.id": 1 }, IndexOptions::default()),
+            (
+                doc! { "channel.id": 1, "user.id": 1 },
+                IndexOptions::builder().unique(true).build(),
+            ),
+            (doc! { "time": 1 }, IndexOptions::default()),
+            (
+                doc! { "expires_at": 1 },
+                IndexOptions::builder().expire_after(Duration::ZERO).build(),
+            ),
+        ],
+    )
+    .await?;
+
+    mongo_ensure_indexes_with_options(
+        db,
+        "quota-posts",
+        vec![
+            (
+                doc! { "channel_id": 1, "user_id": 1, "time": -1 },
+                IndexOptions::default(),
+            ),
+            (
+                doc! { "expires_at": 1 },
+                IndexOptions::builder().expire_after(Duration::ZERO).build(),
+            ),
+        ],
+    )
+    .await?;
+
+    mongo_ensure_indexes(
+        db,
+        "infractions",
+        vec![
+            (doc! { "guild_id": 1, "user_id": 1, "time": -1 }, false),
             (doc! { "time": 1 }, false),
         ],
     )
     .await?;
 
-    mongo_ensure_indexes(db, "starboard", vec![(doc! { "message_id": 1 }, true)]).await?;
+    mongo_ensure_indexes(db, "guild-settings", vec![(doc! { "guild_id": 1 }, true)]).await?;
+
+    mongo_ensure_indexes(
+        db,
+        "autoroles-pending",
+        vec![
+            (doc! { "guild_id": 1, "user_id": 1, "role_id": 1 }, true),
+            (doc! { "due": 1 }, false),
+        ],
+    )
+    .await?;
+
+    // unique per message from before there were multiple boards, which would make starring a
+    // message on a second board fail after it was posted there
+    // ignore error: the index might not exist
+    let _ = db
+        .collection::<Document>("starboard")
+        .drop_index("message_id_1")
+        .await;
+    mongo_ensure_indexes(
+        db,
+        "starboard",
+        vec![
+            (doc! { "message_id": 1, "board": 1 }, true),
+            (doc! { "guild_id": 1, "board": 1, "count": -1 }, false),
+            (
+                doc! { "guild_id": 1, "board": 1, "author_id": 1, "count": -1 },
+                false,
+            ),
+        ],
+    )
+    .await?;
+
+    mongo_ensure_indexes(
+        db,
+        "starboard-queue",
+        vec![
+            (doc! { "message_id": 1 }, true),
+            (doc! { "time": 1 }, false),
+        ],
+    )
+    .await?;
 
     Ok(())
 }
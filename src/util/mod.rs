@@ -1,5 +1,6 @@
 #![allow(dead_code)] // which utilities get used depends on crate features
 
+use chrono::TimeDelta;
 use conv::ConvUtil;
 use itertools::Itertools;
 use std::time::Duration;
@@ -80,6 +81,11 @@ pub fn separate_thousands_floating(n: f64) -> String {
     }
 }
 
+/// Configured seconds as a [`TimeDelta`], or `None` if there are too many to represent.
+pub fn try_seconds(secs: u64) -> Option<TimeDelta> {
+    TimeDelta::try_seconds(i64::try_from(secs).ok()?)
+}
+
 pub fn format_duration_long(duration: &Duration) -> String {
     let hours = duration.as_secs() / 60 / 60;
     let mins = (duration.as_secs() / 60) % 60;
@@ -124,6 +130,13 @@ mod tests {
     use super::ELLIPSIS;
     use std::time::Duration;
 
+    #[test]
+    fn try_seconds() {
+        assert_eq!(super::try_seconds(90), Some(chrono::TimeDelta::seconds(90)));
+        assert_eq!(super::try_seconds(u64::MAX), None);
+        assert_eq!(super::try_seconds(i64::MAX as u64), None);
+    }
+
     #[test]
     fn ellipsis_string() {
         assert_eq!(super::ellipsis_string("testing", 0), "");